@@ -0,0 +1,97 @@
+//! Compile-time conversion of [Dahlia](https://docs.rs/dahlia) format codes.
+//!
+//! The [`dahlia!`] macro converts a string literal for every color depth while
+//! compiling, so no parsing happens at runtime, and reports unknown format codes
+//! as compile errors.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use dahlia::{Dahlia, Depth};
+//! use dahlia_macros::dahlia;
+//!
+//! let greeting = dahlia!("&2Hello, &lworld!");
+//!
+//! let d = Dahlia::default().with_depth(Depth::Low);
+//! assert_eq!(greeting.render(&d), "\x1b[32mHello, \x1b[1mworld!\x1b[0m");
+//! ```
+use dahlia::{Dahlia, Depth};
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, LitChar, LitStr,
+};
+
+struct Input {
+    marker: char,
+    text: LitStr,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let marker = if input.peek(LitChar) {
+            let marker: LitChar = input.parse()?;
+            input.parse::<syn::Token![,]>()?;
+            marker.value()
+        } else {
+            '&'
+        };
+
+        let text = input.parse()?;
+        input.parse::<Option<syn::Token![,]>>()?;
+
+        Ok(Self { marker, text })
+    }
+}
+
+/// Converts a string literal with Dahlia format codes for every color depth.
+///
+/// Expands to a constant [`dahlia::Precompiled`], use its `render` method to get
+/// the string for a Dahlia instance. The marker defaults to `&`, a different one
+/// can be passed as the first argument.
+///
+/// ### Example
+/// ```rust
+/// # use dahlia::{Dahlia, Depth, Precompiled};
+/// # use dahlia_macros::dahlia;
+/// const WARNING: Precompiled = dahlia!('§', "§e§lWarning:§R ");
+///
+/// let d = Dahlia::default().with_depth(Depth::High).with_auto_reset(false);
+/// assert_eq!(WARNING.render(&d), "\x1b[38;2;255;255;85m\x1b[1mWarning:\x1b[0m ");
+/// assert_eq!(WARNING.for_depth(None), "Warning: ");
+/// ```
+///
+/// Unknown codes fail to compile:
+/// ```compile_fail
+/// # use dahlia_macros::dahlia;
+/// let typo = dahlia!("&zHello");
+/// ```
+#[proc_macro]
+pub fn dahlia(input: TokenStream) -> TokenStream {
+    let Input { marker, text } = parse_macro_input!(input as Input);
+    let value = text.value();
+
+    let dahlia = |depth| Dahlia::default().with_marker(marker).with_depth(depth);
+
+    if let Err(error) = dahlia(Depth::High).validate(&value) {
+        let errors = error
+            .codes
+            .iter()
+            .map(|code| syn::Error::new(text.span(), code.to_string()).to_compile_error());
+        // a block, so that several errors are still a valid expression
+        return quote!({ #(#errors)* }).into();
+    }
+
+    let plain = dahlia(Depth::High).clean(&value).into_owned();
+    let [tty, low, medium, high] = [Depth::Tty, Depth::Low, Depth::Medium, Depth::High]
+        .map(|depth| dahlia(depth).convert(&value).into_owned());
+
+    let without_reset = dahlia(Depth::High).with_auto_reset(false).convert(&value);
+    let appended_reset = without_reset != high;
+
+    quote! {
+        ::dahlia::Precompiled::__new(#plain, [#tty, #low, #medium, #high], #appended_reset)
+    }
+    .into()
+}
@@ -0,0 +1,236 @@
+use crate::{
+    color::{palette_rgb, COLOR_CODES},
+    consts::{COLORS_4BIT, COLORS_8BIT, SGR_REGEX},
+    escape_markers,
+    wrap::link_url,
+    Format, Reset, Token,
+};
+
+/// Converts ANSI escape codes in a string to the equivalent Dahlia codes.
+///
+/// SGR sequences (colors, formats and resets, including the extended underline
+/// styles and underline colors) are translated, colors without an exact
+/// palette match become `#rrggbb;` or `@n;` codes, and OSC 8 hyperlinks become
+/// `[link:url]` and `[/link]`. All other escape codes are removed and markers
+/// already present in the text are escaped.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::ansi_to_dahlia;
+/// let ansi = "\x1b[1;31mError:\x1b[0m \x1b[38;2;1;2;3mA&B";
+/// assert_eq!(ansi_to_dahlia(ansi, '&'), "&l&4Error:&R &#010203;A&_B");
+/// ```
+pub fn ansi_to_dahlia(string: &str, marker: char) -> String {
+    let mut dahlia = String::with_capacity(string.len());
+    let mut last_match = 0;
+
+    for captures in SGR_REGEX.captures_iter(string) {
+        let sequence = captures.get(0).expect("group 0 is always present");
+
+        dahlia.push_str(&escape_markers(
+            &string[last_match..sequence.start()],
+            marker,
+        ));
+
+        if let Some(params) = captures.name("params") {
+            for token in sgr_to_tokens(params.as_str()) {
+                dahlia.push_str(&token.to_markup(marker));
+            }
+        } else if let Some(url) = captures.name("osc").and_then(|osc| link_url(osc.as_str())) {
+            let url = escape_url(url);
+            let token = if url.is_empty() {
+                Token::LinkEnd
+            } else {
+                Token::Link(&url)
+            };
+            dahlia.push_str(&token.to_markup(marker));
+        }
+
+        last_match = sequence.end();
+    }
+
+    dahlia.push_str(&escape_markers(&string[last_match..], marker));
+    dahlia
+}
+
+fn sgr_to_tokens(params: &str) -> Vec<Token<'static>> {
+    let mut params = params.split(';');
+    let mut tokens = Vec::new();
+
+    while let Some(param) = params.next() {
+        let subparams: Vec<_> = param.split(':').collect();
+
+        let Some(code) = number(subparams[0]) else {
+            continue;
+        };
+
+        let token = match code {
+            0 => Token::Reset(Reset::All),
+            1 => Token::Format(Format::Bold),
+            2 => Token::Format(Format::Dim),
+            3 => Token::Format(Format::Italic),
+            // the underline style is an optional subparameter (4:3)
+            4 => match subparams.get(1).map(|&style| number(style)) {
+                None | Some(Some(1)) => Token::Format(Format::Underline),
+                Some(Some(0)) => Token::Reset(Reset::Format(Format::Underline)),
+                Some(Some(2)) => Token::Format(Format::DoubleUnderline),
+                Some(Some(3)) => Token::Format(Format::CurlyUnderline),
+                Some(Some(4)) => Token::Format(Format::DottedUnderline),
+                Some(Some(5)) => Token::Format(Format::DashedUnderline),
+                _ => continue,
+            },
+            5 | 6 => Token::Format(Format::Blink),
+            7 => Token::Format(Format::Inverse),
+            8 => Token::Format(Format::Hidden),
+            9 => Token::Format(Format::Strikethrough),
+            21 => Token::Format(Format::DoubleUnderline),
+            // normal intensity, neither bold nor dim
+            22 => {
+                tokens.push(Token::Reset(Reset::Format(Format::Bold)));
+                Token::Reset(Reset::Format(Format::Dim))
+            }
+            23 => Token::Reset(Reset::Format(Format::Italic)),
+            24 => Token::Reset(Reset::Format(Format::Underline)),
+            25 => Token::Reset(Reset::Format(Format::Blink)),
+            27 => Token::Reset(Reset::Format(Format::Inverse)),
+            28 => Token::Reset(Reset::Format(Format::Hidden)),
+            29 => Token::Reset(Reset::Format(Format::Strikethrough)),
+            30..=37 | 90..=97 => basic_color(code, false),
+            40..=47 | 100..=107 => basic_color(code - 10, true),
+            39 => Token::Reset(Reset::Foreground),
+            49 => Token::Reset(Reset::Background),
+            53 => Token::Format(Format::Overline),
+            55 => Token::Reset(Reset::Format(Format::Overline)),
+            59 => Token::Reset(Reset::UnderlineColor),
+            38 | 48 | 58 => {
+                let background = code == 48;
+
+                // either colon separated (38:5:n) or spread over the next parameters (38;5;n)
+                let color = if subparams.len() > 1 {
+                    extended_color(&subparams[1..], background)
+                } else {
+                    extended_color_from(&mut params, background)
+                };
+
+                match color {
+                    Some(token) if code == 58 => underline_color(token),
+                    Some(token) => token,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Turns a foreground color into the same underline color.
+fn underline_color(token: Token<'static>) -> Token<'static> {
+    match token {
+        Token::Color { code, .. } => Token::UnderlineColor { code },
+        Token::Hex { rgb, .. } => Token::UnderlineHex { rgb },
+        Token::Xterm { index, .. } => Token::UnderlineXterm { index },
+        token => token,
+    }
+}
+
+/// Percent-encodes the characters that can't be part of a `[link:url]` code.
+fn escape_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+
+    for ch in url.chars() {
+        if ch == ']' || ch.is_whitespace() {
+            for byte in ch.encode_utf8(&mut [0; 4]).bytes() {
+                escaped.push_str(&format!("%{byte:02X}"));
+            }
+        } else {
+            escaped.push(ch);
+        }
+    }
+
+    escaped
+}
+
+fn number(param: &str) -> Option<u16> {
+    // an empty parameter is the same as 0
+    if param.is_empty() {
+        Some(0)
+    } else {
+        param.parse().ok()
+    }
+}
+
+fn basic_color(code: u16, background: bool) -> Token<'static> {
+    let code = palette_code(COLORS_4BIT, &code.to_string())
+        .expect("every basic color should be in the 4-bit table");
+
+    Token::Color { code, background }
+}
+
+fn extended_color(args: &[&str], background: bool) -> Option<Token<'static>> {
+    match number(args[0])? {
+        5 => {
+            let index = u8::try_from(number(args.get(1)?)?).ok()?;
+            Some(indexed_color(index, background))
+        }
+        // the color space ID before the channels is optional
+        2 if args.len() >= 4 => {
+            let channels = &args[args.len() - 3..];
+            let rgb = [0, 1, 2].map(|i| number(channels[i]).and_then(|n| u8::try_from(n).ok()));
+            let [Some(r), Some(g), Some(b)] = rgb else {
+                return None;
+            };
+            Some(rgb_color([r, g, b], background))
+        }
+        _ => None,
+    }
+}
+
+fn extended_color_from<'a>(
+    params: &mut impl Iterator<Item = &'a str>,
+    background: bool,
+) -> Option<Token<'static>> {
+    let mode = params.next()?;
+    let count = match number(mode)? {
+        5 => 1,
+        2 => 3,
+        _ => return None,
+    };
+
+    let args: Vec<_> = std::iter::once(mode).chain(params.take(count)).collect();
+
+    if args.len() == count + 1 {
+        extended_color(&args, background)
+    } else {
+        None
+    }
+}
+
+fn indexed_color(index: u8, background: bool) -> Token<'static> {
+    // a palette code only if it gives back the same index, otherwise the index
+    // is kept, so the color still follows the terminal's palette
+    match palette_code(COLORS_8BIT, &index.to_string()) {
+        Some(code) => Token::Color { code, background },
+        None => Token::Xterm { index, background },
+    }
+}
+
+fn rgb_color(rgb: [u8; 3], background: bool) -> Token<'static> {
+    match COLOR_CODES
+        .into_iter()
+        .find(|&code| palette_rgb(code) == Some(rgb))
+    {
+        Some(code) => Token::Color { code, background },
+        None => Token::Hex { rgb, background },
+    }
+}
+
+fn palette_code(table: fn(&str) -> Option<&'static str>, value: &str) -> Option<char> {
+    COLOR_CODES
+        .into_iter()
+        .find(|code| table(code.encode_utf8(&mut [0; 4])) == Some(value))
+}
@@ -0,0 +1,214 @@
+//! The `dahlia` command, available with the `cli` feature.
+//!
+//! Runs one of the string functions of the crate on its arguments, or on every
+//! line of stdin if there are none:
+//!
+//! ```sh
+//! dahlia "&aok&R deployed to &lprod"
+//! git log --oneline | dahlia clean
+//! ```
+
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    process::ExitCode,
+};
+
+use dahlia::{clean_ansi, Dahlia, Depth};
+
+const USAGE: &str = "\
+Usage: dahlia [COMMAND] [OPTIONS] [TEXT]...
+
+Formats TEXT, or every line of stdin if no TEXT is given.
+
+Commands:
+  convert     Convert Dahlia codes to ANSI escape codes (default)
+  clean       Remove Dahlia codes
+  escape      Escape the markers
+  clean-ansi  Remove ANSI escape codes
+  preview     Show the colors and formats at every depth, to check what the
+              terminal supports
+
+Options:
+  -d, --depth <DEPTH>  Color depth: tty, low, medium, high or 3, 4, 8, 24
+  -a, --auto           Infer the color depth, leaving colors out when stdout
+                       isn't a terminal or NO_COLOR is set
+  -m, --marker <CHAR>  Marker of the codes [default: &]
+  -n, --no-reset       Don't reset the formatting at the end of every line
+  -h, --help           Print this message
+  -V, --version        Print the version
+
+Without --depth or --auto, the depth is inferred from COLORTERM and TERM, and
+colors are left out if NO_COLOR is set.";
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Command {
+    Convert,
+    Clean,
+    Escape,
+    CleanAnsi,
+    Preview,
+}
+
+struct Options {
+    command: Command,
+    depth: Option<Depth>,
+    auto: bool,
+    marker: char,
+    no_reset: bool,
+    text: Vec<String>,
+}
+
+enum Parsed {
+    Run(Options),
+    Help,
+    Version,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Parsed::Run(options)) => options,
+        Ok(Parsed::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Parsed::Version) => {
+            println!("dahlia {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("dahlia: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        // the reader of the output went away, e.g. `dahlia ... | head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("dahlia: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Parsed, String> {
+    let mut args = args.into_iter().peekable();
+
+    let command = match args.peek().map(String::as_str) {
+        Some("convert") => Some(Command::Convert),
+        Some("clean") => Some(Command::Clean),
+        Some("escape") => Some(Command::Escape),
+        Some("clean-ansi") => Some(Command::CleanAnsi),
+        Some("preview") => Some(Command::Preview),
+        _ => None,
+    };
+    if command.is_some() {
+        args.next();
+    }
+
+    let mut options = Options {
+        command: command.unwrap_or(Command::Convert),
+        depth: None,
+        auto: false,
+        marker: '&',
+        no_reset: false,
+        text: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        // `--depth=8` is the same as `--depth 8`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_owned(), Some(value.to_owned()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("`{name}` needs a value"))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "-V" | "--version" => return Ok(Parsed::Version),
+            "-a" | "--auto" => options.auto = true,
+            "-n" | "--no-reset" => options.no_reset = true,
+            "-d" | "--depth" => {
+                let depth = value(&flag)?;
+                options.depth = Some(
+                    Depth::try_from(depth.as_str())
+                        .map_err(|()| format!("invalid depth `{depth}`"))?,
+                );
+            }
+            "-m" | "--marker" => {
+                let marker = value(&flag)?;
+                let mut chars = marker.chars();
+                options.marker = match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ch,
+                    _ => return Err(format!("the marker must be one character, got `{marker}`")),
+                };
+            }
+            // everything after `--` is text, even if it starts with a dash
+            "--" => {
+                options.text.extend(args);
+                break;
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option `{flag}`"));
+            }
+            _ => options.text.push(arg),
+        }
+    }
+
+    if options.auto && options.depth.is_some() {
+        return Err("`--depth` and `--auto` can't be used together".to_owned());
+    }
+    if options.command == Command::Preview && !options.text.is_empty() {
+        return Err("`preview` doesn't take any text".to_owned());
+    }
+
+    Ok(Parsed::Run(options))
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    let inferred = match options.depth {
+        Some(_) => None,
+        None if options.auto => Depth::try_infer_for(&io::stdout()),
+        None => Depth::try_infer(),
+    };
+    let mut dahlia = Dahlia::new(inferred, !options.no_reset, options.marker);
+    // `Dahlia::new` leaves the colors out if NO_COLOR is set, which an
+    // explicit depth overrides
+    if let Some(depth) = options.depth {
+        dahlia.set_depth(depth);
+    }
+
+    if options.command == Command::Preview {
+        return write!(io::stdout(), "{}", dahlia.preview());
+    }
+
+    let format = |line: &str| match options.command {
+        Command::Convert => dahlia.convert(line).into_owned(),
+        Command::Clean => dahlia.clean(line).into_owned(),
+        Command::Escape => dahlia.escape(line),
+        Command::CleanAnsi => clean_ansi(line).into_owned(),
+        Command::Preview => unreachable!("the preview doesn't format any text"),
+    };
+
+    let mut stdout = io::stdout().lock();
+
+    if !options.text.is_empty() {
+        return writeln!(stdout, "{}", format(&options.text.join(" ")));
+    }
+
+    for line in io::stdin().lock().lines() {
+        writeln!(stdout, "{}", format(&line?))?;
+    }
+
+    Ok(())
+}
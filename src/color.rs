@@ -0,0 +1,238 @@
+use lazy_static::lazy_static;
+
+use crate::{
+    consts::{COLORS_24BIT, CSS_COLORS, PALETTE_NAMES},
+    style::Color,
+};
+
+/// Metric used to pick the nearest palette color when a hex color
+/// has to be downsampled to a lower color depth.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum ColorDistance {
+    /// Euclidean distance in the RGB color space
+    #[default]
+    Euclidean,
+    /// Perceptual CIEDE2000 color difference
+    Ciede2000,
+}
+
+/// The 16 Dahlia color codes, in palette order.
+pub(crate) const COLOR_CODES: [char; 16] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const XTERM_SYSTEM: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+lazy_static! {
+    static ref XTERM_LAB: Vec<[f64; 3]> = (0..=255).map(|i| rgb_to_lab(xterm_rgb(i))).collect();
+}
+
+/// Returns the 24-bit RGB value of a Dahlia color code.
+pub(crate) fn palette_rgb(code: char) -> Option<[u8; 3]> {
+    COLORS_24BIT(code.encode_utf8(&mut [0; 4])).map(|rgb| {
+        rgb.map(|channel| {
+            channel
+                .parse()
+                .expect("color tables should contain valid numbers")
+        })
+    })
+}
+
+/// Looks up a color by name, palette names (e.g. `light_red`) take
+/// precedence over CSS names.
+pub(crate) fn named_color(name: &str) -> Option<Color> {
+    if let Some(index) = PALETTE_NAMES.iter().position(|&palette| palette == name) {
+        return Some(Color::Palette(COLOR_CODES[index]));
+    }
+
+    CSS_COLORS
+        .iter()
+        .find(|(css, _)| *css == name)
+        .map(|&(_, rgb)| Color::Rgb(rgb))
+}
+
+/// Returns the RGB value of an xterm-256 color index.
+///
+/// The first 16 (system) colors use the xterm defaults.
+pub(crate) fn xterm_rgb(index: u8) -> [u8; 3] {
+    match index {
+        0..=15 => XTERM_SYSTEM[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            [i / 36, (i / 6) % 6, i % 6].map(|level| CUBE_LEVELS[level as usize])
+        }
+        232..=255 => [8 + 10 * (index - 232); 3],
+    }
+}
+
+/// Finds the Dahlia color code whose color in `palette` is closest to `rgb`.
+///
+/// With `basic_only`, only the first 8 codes (which have distinct 3-bit
+/// equivalents) are considered.
+pub(crate) fn nearest_palette(
+    rgb: [u8; 3],
+    basic_only: bool,
+    distance: ColorDistance,
+    palette: &[[u8; 3]; 16],
+) -> char {
+    let count = if basic_only { 8 } else { 16 };
+
+    let index = nearest(
+        rgb,
+        distance,
+        0..count,
+        |i| palette[i],
+        |i| rgb_to_lab(palette[i]),
+    );
+
+    COLOR_CODES[index]
+}
+
+/// Finds the xterm-256 color index closest to `rgb`.
+///
+/// The 16 system colors are skipped, since terminals commonly remap them.
+pub(crate) fn nearest_xterm(rgb: [u8; 3], distance: ColorDistance) -> u8 {
+    let index = nearest(
+        rgb,
+        distance,
+        16..256,
+        |i| xterm_rgb(i as u8),
+        |i| XTERM_LAB[i],
+    );
+
+    index as u8
+}
+
+fn nearest(
+    rgb: [u8; 3],
+    distance: ColorDistance,
+    candidates: std::ops::Range<usize>,
+    candidate_rgb: impl Fn(usize) -> [u8; 3],
+    candidate_lab: impl Fn(usize) -> [f64; 3],
+) -> usize {
+    let lab = rgb_to_lab(rgb);
+
+    let score = |i: usize| match distance {
+        ColorDistance::Euclidean => euclidean_squared(rgb, candidate_rgb(i)) as f64,
+        ColorDistance::Ciede2000 => ciede2000(lab, candidate_lab(i)),
+    };
+
+    candidates
+        .min_by(|&a, &b| score(a).total_cmp(&score(b)))
+        .expect("there should always be at least one candidate")
+}
+
+fn euclidean_squared(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b)
+        .map(|(&x, y)| (i32::from(x) - i32::from(y)).unsigned_abs().pow(2))
+        .sum()
+}
+
+/// Converts an sRGB color to CIELAB (D65 white point).
+fn rgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(|channel| {
+        let c = f64::from(channel) / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| {
+        const DELTA: f64 = 6.0 / 29.0;
+        if t > DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIEDE2000 color difference between two CIELAB colors.
+pub(crate) fn ciede2000([l1, a1, b1]: [f64; 3], [l2, a2, b2]: [f64; 3]) -> f64 {
+    let pow7 = |x: f64| x.powi(7);
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt());
+
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 > h1 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t =
+        1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+            - 0.20 * cos(4.0 * h_bar - 63.0);
+
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
@@ -2,8 +2,6 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::Depth;
-
 pub fn formatter(name: &str) -> Option<&'static [&'static str]> {
     match name {
         "h" => Some(&["8"]),         // hidden
@@ -14,6 +12,11 @@ pub fn formatter(name: &str) -> Option<&'static [&'static str]> {
         "m" => Some(&["9"]),         // strikethrough
         "n" => Some(&["4"]),         // underline
         "o" => Some(&["3"]),         // italic
+        "s" => Some(&["4:2"]),       // double underline
+        "t" => Some(&["4:3"]),       // curly underline
+        "u" => Some(&["4:4"]),       // dotted underline
+        "v" => Some(&["4:5"]),       // dashed underline
+        "w" => Some(&["53"]),        // overline
         "R" => Some(&["0"]),         // full reset
         "rf" => Some(&["39"]),       // reset foreground
         "rb" => Some(&["49"]),       // reset background
@@ -26,6 +29,12 @@ pub fn formatter(name: &str) -> Option<&'static [&'static str]> {
         "rm" => Some(&["29"]),       // reset strikethrough
         "rn" => Some(&["24"]),       // reset underline
         "ro" => Some(&["23"]),       // reset italic
+        "rs" => Some(&["24"]),       // reset double underline
+        "rt" => Some(&["24"]),       // reset curly underline
+        "ru" => Some(&["24"]),       // reset dotted underline
+        "rv" => Some(&["24"]),       // reset dashed underline
+        "rw" => Some(&["55"]),       // reset overline
+        "r^" => Some(&["59"]),       // reset underline color
         _ => None,
     }
 }
@@ -118,46 +127,228 @@ pub fn COLORS_24BIT(name: &str) -> Option<[&'static str; 3]> {
     }
 }
 
-pub fn fmt_template(name: Depth) -> &'static str {
-    match name {
-        Depth::Tty => "\x1b[{}m",
-        Depth::Low => "\x1b[{}m",
-        Depth::Medium => "\x1b[38;5;{}m",
-        Depth::High => "\x1b[38;2;{r};{g};{b}m",
-    }
-}
-
-pub fn fmt_background_template(name: Depth) -> &'static str {
-    match name {
-        Depth::Tty => "\x1b[{}m",
-        Depth::Low => "\x1b[{}m",
-        Depth::Medium => "\x1b[48;5;{}m",
-        Depth::High => "\x1b[48;2;{r};{g};{b}m",
-    }
-}
-
-pub(crate) type ColorCodeMapper = dyn Fn(&str) -> Option<&'static str>;
-pub fn colors(name: Depth) -> Option<&'static ColorCodeMapper> {
-    match name {
-        Depth::Tty => Some(&COLORS_3BIT),
-        Depth::Low => Some(&COLORS_4BIT),
-        Depth::Medium => Some(&COLORS_8BIT),
-        _ => None,
-    }
-}
-
 fn re(string: &str) -> Regex {
     Regex::new(string).expect("Hard coded regexes are always valid.")
 }
 
 lazy_static! {
-    // From spec (https://github.com/dahlia-lib/spec/blob/v1.0.0/SPECIFICATION.md#clean_ansi)
-    pub static ref ANSI_REGEX: Regex = re(r"[\u001B\u009B][\[\]()#;?]*(?:(?:(?:(?:;[-a-zA-Z\d\/#&.:=?%@~_]+)*|[a-zA-Z\d]+(?:;[-a-zA-Z\d\/#&.:=?%@~_]*)*)?\u0007)|(?:(?:\d{1,4}(?:;\d{0,4})*)?[\dA-PR-TZcf-nq-uy=><~]))");
+    // From spec (https://github.com/dahlia-lib/spec/blob/v1.0.0/SPECIFICATION.md#clean_ansi),
+    // preceded by OSC sequences (e.g. OSC 8 hyperlinks) with any content, which
+    // the spec only covers for a few characters and without the ST terminator
+    pub static ref ANSI_REGEX: Regex = re(r"(?:\u001B\]|\u009D)(?<osc>[^\u0007\u001B\u009C]*)(?:\u0007|\u001B\\|\u009C)|[\u001B\u009B][\[\]()#;?]*(?:(?:(?:(?:;[-a-zA-Z\d\/#&.:=?%@~_]+)*|[a-zA-Z\d]+(?:;[-a-zA-Z\d\/#&.:=?%@~_]*)*)?\u0007)|(?:(?:\d{1,4}(?:;\d{0,4})*)?[\dA-PR-TZcf-nq-uy=><~]))");
+
+    // SGR sequences (with their parameters) or any other ANSI escape code
+    pub static ref SGR_REGEX: Regex = re(&format!(
+        r"(?:\u001B\[|\u009B)(?<params>[\d;:]*)m|{}",
+        ANSI_REGEX.as_str()
+    ));
 
     pub static ref CODE_REGEX: String = format!(
-        "(?<bg>~)?(?:{colors}|{hex})|{formatters}",
+        "(?:(?<bg>~)|(?<ul>\\^))?(?:{colors}|{hex}|{xterm})|{formatters}",
         colors = r"(?<color>[0-9a-f])",
         hex = r"#(?<hex>[0-9a-f]{3}|[0-9a-f]{6});",
-        formatters = r"(?<fmt>[h-oR]|r[bcfh-o])"
+        xterm = r"@(?<xterm>25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d);",
+        formatters = r"(?<fmt>[h-os-wR]|r[bcfh-os-w^])"
+    );
+
+    // `[name]` with any palette or CSS color name, see `Dahlia::with_named_colors`
+    pub static ref NAMED_COLOR_REGEX: String = format!(
+        r"(?<name>[~^]?\[(?:{})\])",
+        PALETTE_NAMES
+            .iter()
+            .chain(CSS_COLORS.iter().map(|(name, _)| name))
+            .copied()
+            .collect::<Vec<_>>()
+            .join("|")
+    );
+
+    // `[gradient:#xxx:#xxxxxx...]` with two or more colors, or `[rainbow]`
+    pub static ref GRADIENT_REGEX: String = format!(
+        r"(?<gradient>(?<gbg>~)?\[(?:gradient(?<stops>(?::#{hex}){{2,}})|rainbow)\])",
+        hex = "(?:[0-9a-f]{3}|[0-9a-f]{6})"
+    );
+
+    // `[link:url]` and `[/link]`
+    pub static ref LINK_REGEX: String =
+        r"(?<link>\[link:(?<url>[^\]\s]+)\])|(?<linkend>\[/link\])".to_owned();
+
+    // codes of Dahlia 1.x, see `SpecVersion::Dahlia1`
+    pub static ref DAHLIA1_CODE_REGEX: String = format!(
+        "(?<bg>~)?(?:{colors}|{hex})|{formatters}",
+        colors = r"(?<color>[0-9a-g])",
+        hex = r"\[#(?<hex>[0-9a-fA-F]{6})\]",
+        formatters = r"(?<dahlia1>[i-pr])"
     );
 }
+
+// names of the palette colors, in palette order
+pub const PALETTE_NAMES: [&str; 16] = [
+    "black",
+    "blue",
+    "green",
+    "cyan",
+    "red",
+    "purple",
+    "orange",
+    "light_gray",
+    "gray",
+    "light_blue",
+    "light_green",
+    "light_cyan",
+    "light_red",
+    "light_purple",
+    "yellow",
+    "white",
+];
+
+// CSS named colors (https://www.w3.org/TR/css-color-4/#named-colors)
+pub const CSS_COLORS: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::{color::named_color, scan, SpecVersion, Token};
+
+// longest name of a defined code, so the writer can still hold back `[name`
+pub(crate) const MAX_NAME_LEN: usize = 20;
+
+/// A code registered with [`Dahlia::define`](crate::Dahlia::define) or
+/// [`Dahlia::define_sgr`](crate::Dahlia::define_sgr).
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Definition {
+    /// Built-in codes the definition expands to
+    pub tokens: Vec<Token<'static>>,
+    /// Raw SGR parameters, written after the codes
+    pub sgr: Vec<String>,
+}
+
+/// Returns the pattern matching every defined code, if there are any.
+///
+/// Single characters are used as they are (`&p`), longer names in brackets
+/// (`&[error]`).
+pub(crate) fn definitions_regex(definitions: &HashMap<String, Definition>) -> Option<String> {
+    let mut names: Vec<_> = definitions.keys().collect();
+    // longest first, so that no name shadows another one starting the same way
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+    let alternatives: Vec<_> = names
+        .into_iter()
+        .map(|name| {
+            if name.chars().count() == 1 {
+                regex::escape(name)
+            } else {
+                format!(r"\[{}\]", regex::escape(name))
+            }
+        })
+        .collect();
+
+    (!alternatives.is_empty()).then(|| format!("(?<custom>{})", alternatives.join("|")))
+}
+
+/// Returns the name of a defined code matched by [`definitions_regex`].
+pub(crate) fn name(code: &str) -> &str {
+    code.strip_prefix('[')
+        .and_then(|code| code.strip_suffix(']'))
+        .unwrap_or(code)
+}
+
+/// Checks that `name` can be used for a defined code with the codes of
+/// `spec`, returning why not.
+///
+/// Single characters can't be or start a built-in code (`&4`, `&r` of
+/// `&rb`), and longer names can't be a gradient or a color name, so a
+/// definition never hides a built-in code. Definitions are checked again
+/// when the spec version changes.
+pub(crate) fn check_name(name: &str, spec: SpecVersion) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!(
+            "names of defined codes must be 1 to {MAX_NAME_LEN} characters long"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+    {
+        return Err(
+            "names of defined codes can only contain ASCII letters, digits, `_` and `-`".to_owned(),
+        );
+    }
+
+    match name.as_bytes() {
+        [byte] => {
+            let builtin = match spec {
+                SpecVersion::Dahlia1 => {
+                    matches!(byte, b'0'..=b'9' | b'a'..=b'g' | b'i'..=b'p' | b'r' | b'_')
+                }
+                SpecVersion::Dahlia2 => scan::starts_code(*byte),
+            };
+            if builtin {
+                return Err(format!(
+                    "`{name}` is a built-in code or starts one, and can't be redefined"
+                ));
+            }
+        }
+        _ if name == "rainbow" => {
+            return Err("`rainbow` is a built-in code and can't be redefined".to_owned());
+        }
+        _ if named_color(name).is_some() => {
+            return Err(format!(
+                "`{name}` is a color name (see `Dahlia::set_named_colors`) and can't be redefined"
+            ));
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Checks that `params` are SGR parameters: numbers separated by `;` or `:`,
+/// so that no other escape sequence can be written with them.
+pub(crate) fn check_sgr(params: &str) -> Result<(), String> {
+    let valid = params
+        .split([';', ':'])
+        .all(|param| !param.is_empty() && param.bytes().all(|byte| byte.is_ascii_digit()));
+
+    if valid {
+        Ok(())
+    } else {
+        Err("SGR parameters are numbers separated by `;` or `:`, e.g. `6` or `4:3`".to_owned())
+    }
+}
@@ -1,4 +1,4 @@
-use std::env;
+use std::{env, io::IsTerminal};
 
 /// Supported color depths
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
@@ -39,6 +39,31 @@ impl Depth {
             _ => Some(Self::Low),
         }
     }
+
+    /// Like [`Depth::try_infer`], but for output written to `stream`.
+    ///
+    /// Returns `None` if `stream` isn't a terminal (e.g. when it is redirected
+    /// to a file) or if `NO_COLOR` is set.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let depth = Depth::try_infer_for(&std::io::stderr());
+    /// let dahlia = Dahlia::new(depth, true, '&');
+    /// eprintln!("{}", dahlia.convert("&cerror:&R something went wrong"));
+    /// ```
+    pub fn try_infer_for(stream: &impl IsTerminal) -> Option<Depth> {
+        if no_color() || !stream.is_terminal() {
+            None
+        } else {
+            Self::try_infer()
+        }
+    }
+}
+
+/// Whether colors are turned off with a non-empty `NO_COLOR` variable.
+pub(crate) fn no_color() -> bool {
+    env::var("NO_COLOR").is_ok_and(|value| !value.is_empty())
 }
 
 impl TryFrom<u8> for Depth {
@@ -0,0 +1,294 @@
+use std::{error::Error, fmt};
+
+use crate::{Dahlia, SpecVersion};
+
+/// Error returned by [`Dahlia::try_convert`](crate::Dahlia::try_convert) and
+/// [`Dahlia::validate`](crate::Dahlia::validate), listing every invalid code.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DahliaError {
+    pub codes: Vec<InvalidCode>,
+}
+
+/// A marker that isn't followed by a valid code.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InvalidCode {
+    /// Byte offset of the marker
+    pub offset: usize,
+    /// The invalid code, including the marker
+    pub code: String,
+    /// How the code could be fixed
+    pub suggestion: String,
+}
+
+impl fmt::Display for InvalidCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid code `{}` at byte {}: {}",
+            self.code, self.offset, self.suggestion
+        )
+    }
+}
+
+impl fmt::Display for DahliaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, code) in self.codes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{code}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DahliaError {}
+
+/// Error returned when loading a [`Theme`](crate::Theme) from a file, or
+/// setting a color it can't have.
+#[derive(Debug)]
+pub enum ThemeError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    /// The file isn't valid TOML or JSON, or has an unexpected structure
+    Syntax(String),
+    /// A key or value doesn't describe a palette color
+    Invalid(String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "couldn't read the theme: {error}"),
+            Self::Syntax(message) => write!(f, "malformed theme: {message}"),
+            Self::Invalid(message) => write!(f, "invalid theme: {message}"),
+        }
+    }
+}
+
+impl Error for ThemeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ThemeError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl InvalidCode {
+    /// Describes the invalid code starting at the marker at `offset`,
+    /// `rest` is the text following the marker.
+    pub(crate) fn new(offset: usize, rest: &str, dahlia: &Dahlia) -> Self {
+        let marker = dahlia.marker;
+        let escape = format!("use `{marker}_` for a literal `{marker}`");
+
+        let defined_names = dahlia.definitions.keys().any(|name| name.len() > 1);
+        if dahlia.named_colors || defined_names {
+            if let Some(name) = unknown_name(rest) {
+                let suggestion = match (dahlia.named_colors, defined_names) {
+                    (true, false) => "unknown color name, use a palette or CSS color name",
+                    (false, true) => "unknown name, use the name of a defined code",
+                    _ => "unknown name, use a color name or the name of a defined code",
+                };
+
+                return Self {
+                    offset,
+                    code: format!("{marker}{name}"),
+                    suggestion: suggestion.to_owned(),
+                };
+            }
+        }
+
+        if dahlia.spec == SpecVersion::Dahlia1 {
+            return Self::new_dahlia1(offset, marker, rest, &escape);
+        }
+
+        let code = rest.strip_prefix('~').unwrap_or(rest);
+        let suggestion = if code.starts_with("[gradient") || code.starts_with("[rainbow") {
+            Some(format!(
+                "gradients are written as `{marker}[gradient:#rrggbb:#rrggbb]`, with two or \
+                 more lowercase hex colors, or `{marker}[rainbow]`"
+            ))
+        } else if rest.starts_with("[link") || rest.starts_with("[/link") {
+            Some(format!(
+                "links are written as `{marker}[link:url]text{marker}[/link]`, URLs can't \
+                 contain whitespace or `]`"
+            ))
+        } else {
+            None
+        };
+
+        if let Some(suggestion) = suggestion {
+            // up to the closing bracket, unless whitespace comes first
+            let len = match rest.find(|ch: char| ch == ']' || ch.is_whitespace()) {
+                Some(end) if rest[end..].starts_with(']') => end + 1,
+                Some(end) => end,
+                None => rest.len(),
+            };
+
+            return Self {
+                offset,
+                code: format!("{marker}{}", &rest[..len]),
+                suggestion,
+            };
+        }
+
+        let (code, suggestion) = match rest.chars().next() {
+            None => ("", format!("the string ends with a marker, {escape}")),
+            Some(prefix @ ('~' | '^')) => match rest[1..].chars().next() {
+                Some('#') => {
+                    let (code, suggestion) = diagnose_hex(&rest[1..], &format!("{marker}{prefix}"));
+                    (&rest[..code.len() + 1], suggestion)
+                }
+                Some('@') => {
+                    let (code, suggestion) =
+                        diagnose_xterm(&rest[1..], &format!("{marker}{prefix}"));
+                    (&rest[..code.len() + 1], suggestion)
+                }
+                _ => (
+                    with_suffix(rest, 1),
+                    format!(
+                        "`{prefix}` must be followed by a color code (0-9, a-f), a hex color \
+                         or an xterm-256 color"
+                    ),
+                ),
+            },
+            Some('#') => diagnose_hex(rest, &marker.to_string()),
+            Some('@') => diagnose_xterm(rest, &marker.to_string()),
+            // `[#rrggbb]` is the hex syntax of Dahlia 1.x
+            Some('[') if rest[1..].starts_with('#') => (
+                "[",
+                format!("hex colors are written as `{marker}#rrggbb;` since Dahlia 2.0"),
+            ),
+            Some('[') => match unknown_name(rest) {
+                Some(name) => (
+                    name,
+                    "named colors aren't enabled (see `Dahlia::set_named_colors`) and no code \
+                     is defined with this name"
+                        .to_owned(),
+                ),
+                None => ("[", format!("unknown code, {escape}")),
+            },
+            Some('r') => (
+                with_suffix(rest, 1),
+                "reset codes are `R` or `r` followed by one of b, c, f, h-o, s-w or `^`".to_owned(),
+            ),
+            Some(ch) => (
+                &rest[..ch.len_utf8()],
+                match ch {
+                    'A'..='F' | 'H'..='O' | 'S'..='W' => format!(
+                        "codes are case-sensitive, did you mean `{marker}{}`?",
+                        ch.to_ascii_lowercase()
+                    ),
+                    'p' => format!("blink is `{marker}k` since Dahlia 2.0"),
+                    'g' => format!("`{marker}g` was removed in Dahlia 2.0"),
+                    _ => format!("unknown code, {escape}"),
+                },
+            ),
+        };
+
+        Self {
+            offset,
+            code: format!("{marker}{code}"),
+            suggestion,
+        }
+    }
+
+    fn new_dahlia1(offset: usize, marker: char, rest: &str, escape: &str) -> Self {
+        let (code, suggestion) = match rest.chars().next() {
+            None => ("", format!("the string ends with a marker, {escape}")),
+            Some('[' | '#' | '~') if rest.trim_start_matches('~').starts_with(['[', '#']) => {
+                // up to the closing bracket, if it's close enough to be part of the code
+                let len = rest.find(']').filter(|&i| i < 10).map_or(1, |i| i + 1);
+                (
+                    &rest[..len],
+                    format!("hex colors are written as `{marker}[#rrggbb]` in Dahlia 1.x"),
+                )
+            }
+            Some(ch) => (&rest[..ch.len_utf8()], format!("unknown code, {escape}")),
+        };
+
+        Self {
+            offset,
+            code: format!("{marker}{code}"),
+            suggestion,
+        }
+    }
+}
+
+/// Returns the `[name]` code at the start of `rest`, if it looks like a color name.
+fn unknown_name(rest: &str) -> Option<&str> {
+    let name = rest
+        .strip_prefix(['~', '^'])
+        .unwrap_or(rest)
+        .strip_prefix('[')?;
+    let end = name.find(']')?;
+
+    name[..end]
+        .chars()
+        .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+        .then(|| &rest[..rest.len() - name.len() + end + 1])
+}
+
+/// Returns the first `len` bytes of `code`, and the next character if it
+/// could be part of the code.
+fn with_suffix(code: &str, len: usize) -> &str {
+    match code[len..].chars().next() {
+        Some(ch) if ch.is_ascii_alphanumeric() => &code[..len + 1],
+        _ => &code[..len],
+    }
+}
+
+/// Diagnoses a malformed xterm-256 code, `code` starts at the `@`.
+fn diagnose_xterm<'a>(code: &'a str, prefix: &str) -> (&'a str, String) {
+    let digits_len = code[1..]
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(code.len() - 1);
+    let digits = &code[1..1 + digits_len];
+    let terminated = code[1 + digits_len..].starts_with(';');
+
+    let code = &code[..1 + digits_len + usize::from(terminated)];
+
+    let suggestion = match digits.parse::<u8>() {
+        Ok(index) if terminated => {
+            format!("indices are written without leading zeros, did you mean `{prefix}@{index};`?")
+        }
+        Ok(index) => format!("xterm-256 colors end with `;`, did you mean `{prefix}@{index};`?"),
+        Err(_) => format!("xterm-256 colors are written as `{prefix}@n;`, with n from 0 to 255"),
+    };
+
+    (code, suggestion)
+}
+
+/// Diagnoses a malformed hex code, `code` starts at the `#`.
+fn diagnose_hex<'a>(code: &'a str, prefix: &str) -> (&'a str, String) {
+    let digits_len = code[1..]
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(code.len() - 1);
+    let digits = &code[1..1 + digits_len];
+    let terminated = code[1 + digits_len..].starts_with(';');
+
+    let code = &code[..1 + digits_len + usize::from(terminated)];
+
+    let suggestion = if !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        "hex colors can only contain the digits 0-9 and a-f".to_owned()
+    } else if digits.len() != 3 && digits.len() != 6 {
+        "hex colors need exactly 3 or 6 digits".to_owned()
+    } else {
+        let fixed = format!("{prefix}#{};", digits.to_ascii_lowercase());
+
+        if terminated {
+            format!("hex digits must be lowercase, did you mean `{fixed}`?")
+        } else {
+            format!("hex colors end with `;`, did you mean `{fixed}`?")
+        }
+    };
+
+    (code, suggestion)
+}
@@ -0,0 +1,246 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    parse::parse_hex,
+    scan::{Code, Scanner},
+    Reset, Token,
+};
+
+/// The colors a gradient goes through.
+#[derive(Debug, Clone)]
+enum Stops {
+    /// `[rainbow]`, every hue once
+    Rainbow,
+    /// `[gradient:#xxxxxx:#xxxxxx...]`, evenly spaced colors
+    Colors(Vec<[u8; 3]>),
+}
+
+/// A gradient code and the text it is spread over.
+#[derive(Debug, Clone)]
+pub(crate) struct Gradient {
+    stops: Stops,
+    background: bool,
+    // number of graphemes the gradient is spread over
+    len: usize,
+    // number of graphemes colored so far
+    index: usize,
+    // the color given to the previous grapheme
+    last: Option<[u8; 3]>,
+}
+
+impl Gradient {
+    /// Builds a gradient from the `:#xxx:#xxx` stops of its code (none for
+    /// the rainbow), spread over the graphemes of `rest` up to the code that
+    /// ends it.
+    pub fn new(stops: Option<&str>, background: bool, rest: &str, scanner: Scanner<'_>) -> Self {
+        let stops = match stops {
+            Some(stops) => Stops::Colors(
+                stops[1..]
+                    .split(':')
+                    .map(|stop| parse_hex(&stop[1..]))
+                    .collect(),
+            ),
+            None => Stops::Rainbow,
+        };
+
+        let mut gradient = Self {
+            stops,
+            background,
+            len: 0,
+            index: 0,
+            last: None,
+        };
+        gradient.len = gradient.measure(rest, scanner);
+        gradient
+    }
+
+    /// Whether `token` ends the gradient, by setting or resetting its color.
+    pub fn ends_at(&self, token: &Token<'_>) -> bool {
+        ends_at(self.background, token)
+    }
+
+    /// Returns the color of the next grapheme, or `None` if it keeps the
+    /// color of the previous one.
+    fn next(&mut self, grapheme: &str) -> Option<Token<'static>> {
+        // a foreground color doesn't show on whitespace, so it isn't spent on it
+        if !self.background && grapheme.chars().all(char::is_whitespace) {
+            return None;
+        }
+
+        let rgb = match &self.stops {
+            Stops::Rainbow => hue(360.0 * self.index as f64 / self.len as f64),
+            Stops::Colors(stops) => {
+                let t = if self.len > 1 {
+                    self.index as f64 / (self.len - 1) as f64
+                } else {
+                    0.0
+                };
+                interpolate(stops, t)
+            }
+        };
+        self.index += 1;
+
+        if self.last == Some(rgb) {
+            return None;
+        }
+        self.last = Some(rgb);
+
+        Some(Token::Hex {
+            rgb,
+            background: self.background,
+        })
+    }
+
+    /// Counts the graphemes of `rest` the gradient is spread over.
+    fn measure(&self, rest: &str, scanner: Scanner<'_>) -> usize {
+        let visible = |text: &str| {
+            text.graphemes(true)
+                .filter(|grapheme| self.background || !grapheme.chars().all(char::is_whitespace))
+                .count()
+        };
+
+        let mut len = 0;
+        let mut position = 0;
+
+        for (code, span) in scanner.codes(rest) {
+            len += visible(&rest[position..span.start]);
+            position = span.end;
+
+            let ends = match code {
+                Code::Token(Token::Escape) => {
+                    len += 1;
+                    false
+                }
+                Code::Gradient { background, .. } => background == self.background,
+                Code::Defined(name) => scanner.definitions[name]
+                    .tokens
+                    .iter()
+                    .any(|token| self.ends_at(token)),
+                Code::Token(token) => self.ends_at(&token),
+            };
+
+            if ends {
+                return len;
+            }
+        }
+
+        len + visible(&rest[position..])
+    }
+}
+
+/// The foreground and background gradients active while parsing.
+#[derive(Debug, Default)]
+pub(crate) struct Gradients {
+    foreground: Option<Gradient>,
+    background: Option<Gradient>,
+}
+
+impl Gradients {
+    pub fn start(&mut self, gradient: Gradient) {
+        if gradient.background {
+            self.background = Some(gradient);
+        } else {
+            self.foreground = Some(gradient);
+        }
+    }
+
+    /// Stops the gradients ended by `token`.
+    pub fn end_at(&mut self, token: &Token<'_>) {
+        for gradient in [&mut self.foreground, &mut self.background] {
+            if gradient
+                .as_ref()
+                .is_some_and(|gradient| gradient.ends_at(token))
+            {
+                *gradient = None;
+            }
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.foreground.is_some() || self.background.is_some()
+    }
+
+    /// Returns the colors of the next grapheme.
+    pub fn next(&mut self, grapheme: &str) -> impl Iterator<Item = Token<'static>> {
+        [&mut self.foreground, &mut self.background]
+            .map(|gradient| {
+                gradient
+                    .as_mut()
+                    .and_then(|gradient| gradient.next(grapheme))
+            })
+            .into_iter()
+            .flatten()
+    }
+
+    /// Splits `text` into runs of graphemes (`None`), each preceded by its
+    /// colors.
+    ///
+    /// Ranges are relative to `text`, colors are given the empty range at the
+    /// start of their grapheme.
+    pub fn paint(
+        &mut self,
+        text: &str,
+        mut emit: impl FnMut(Option<Token<'static>>, Range<usize>),
+    ) {
+        let mut run_start = 0;
+
+        for (start, grapheme) in text.grapheme_indices(true) {
+            let mut colors = self.next(grapheme).peekable();
+
+            if colors.peek().is_some() && start > run_start {
+                emit(None, run_start..start);
+                run_start = start;
+            }
+            for color in colors {
+                emit(Some(color), start..start);
+            }
+        }
+
+        if text.len() > run_start {
+            emit(None, run_start..text.len());
+        }
+    }
+}
+
+/// Whether `token` ends a foreground or background gradient.
+pub(crate) fn ends_at(background: bool, token: &Token<'_>) -> bool {
+    match *token {
+        Token::Color { background: bg, .. }
+        | Token::Hex { background: bg, .. }
+        | Token::Xterm { background: bg, .. } => bg == background,
+        Token::Reset(Reset::All | Reset::Color) => true,
+        Token::Reset(Reset::Foreground) => !background,
+        Token::Reset(Reset::Background) => background,
+        _ => false,
+    }
+}
+
+/// Linearly interpolates between evenly spaced colors, `t` goes from 0 to 1.
+fn interpolate(stops: &[[u8; 3]], t: f64) -> [u8; 3] {
+    let position = t * (stops.len() - 1) as f64;
+    // the last stop is reached at the end of the previous segment
+    let segment = (position as usize).min(stops.len() - 2);
+    let t = position - segment as f64;
+
+    let (from, to) = (stops[segment], stops[segment + 1]);
+    [0, 1, 2]
+        .map(|i| (f64::from(from[i]) + (f64::from(to[i]) - f64::from(from[i])) * t).round() as u8)
+}
+
+/// Returns the fully saturated color of a hue, in degrees.
+fn hue(degrees: f64) -> [u8; 3] {
+    let sector = degrees / 60.0;
+    let x = 1.0 - (sector % 2.0 - 1.0).abs();
+
+    let [r, g, b] = match sector as u8 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    };
+    [r, g, b].map(|channel: f64| (channel * 255.0).round() as u8)
+}
@@ -0,0 +1,278 @@
+use std::fmt::Write;
+
+use crate::{
+    color::COLOR_CODES,
+    style::{Color, Style},
+    Format, Spanned, Theme, Token,
+};
+
+const CLASS_PREFIX: &str = "dahlia-";
+const DECORATION_PROPERTY: &str = "text-decoration-line";
+
+// formats rendered with text-decoration-line, combined when several are active,
+// `Format::Underline` stands for every underline style
+const DECORATIONS: [(Format, &str); 4] = [
+    (Format::Underline, "underline"),
+    (Format::Overline, "overline"),
+    (Format::Strikethrough, "line-through"),
+    (Format::Blink, "blink"),
+];
+
+/// Renders parsed Dahlia tokens as HTML, with either inline styles or classes.
+pub(crate) fn render<'a>(
+    tokens: impl Iterator<Item = Spanned<'a>>,
+    marker: char,
+    classes: bool,
+    theme: &Theme,
+) -> String {
+    let mut html = String::new();
+    let mut style = Style::default();
+    // style of the currently open <span>, plain if there is none
+    let mut open = Style::default();
+    let mut link_open = false;
+
+    for Spanned { token, .. } in tokens {
+        let mut marker_buffer = [0; 4];
+
+        let text = match token {
+            Token::Text(text) => text,
+            Token::Escape => marker.encode_utf8(&mut marker_buffer),
+            Token::Link(_) | Token::LinkEnd => {
+                // the <span> is reopened inside or after the <a>
+                if !open.is_plain() {
+                    html.push_str("</span>");
+                    open = Style::default();
+                }
+                if link_open {
+                    html.push_str("</a>");
+                }
+
+                link_open = matches!(token, Token::Link(_));
+                if let Token::Link(url) = token {
+                    html.push_str("<a href=\"");
+                    escape_into(&mut html, url);
+                    html.push_str("\">");
+                }
+                continue;
+            }
+            _ => {
+                style.apply(&token);
+                continue;
+            }
+        };
+
+        if style != open {
+            if !open.is_plain() {
+                html.push_str("</span>");
+            }
+            if !style.is_plain() {
+                open_span(&mut html, &style, classes, theme);
+            }
+            open = style;
+        }
+
+        escape_into(&mut html, text);
+    }
+
+    if !open.is_plain() {
+        html.push_str("</span>");
+    }
+    if link_open {
+        html.push_str("</a>");
+    }
+
+    html
+}
+
+/// Returns the CSS rules for the classes emitted by [`Dahlia::to_html_classes`](crate::Dahlia::to_html_classes),
+/// with the colors of the default [`Theme`] (see [`Theme::html_stylesheet`]).
+///
+/// ### Example
+/// ```rust
+/// # use dahlia::html_stylesheet;
+/// assert!(html_stylesheet().contains(".dahlia-a { color: #55ff55; }"));
+/// ```
+pub fn html_stylesheet() -> String {
+    stylesheet(&Theme::default())
+}
+
+pub(crate) fn stylesheet(theme: &Theme) -> String {
+    let mut css = String::new();
+
+    for code in COLOR_CODES {
+        let color = Color::Palette(code).css(theme);
+        let _ = writeln!(css, ".{CLASS_PREFIX}{code} {{ color: {color}; }}");
+        let _ = writeln!(
+            css,
+            ".{CLASS_PREFIX}bg-{code} {{ background-color: {color}; }}"
+        );
+        let _ = writeln!(
+            css,
+            ".{CLASS_PREFIX}ul-{code} {{ text-decoration-color: {color}; }}"
+        );
+    }
+
+    for format in Format::ALL {
+        if let Some((property, value)) = declaration(format) {
+            let _ = writeln!(
+                css,
+                ".{CLASS_PREFIX}{} {{ {property}: {value}; }}",
+                format.name()
+            );
+        }
+    }
+
+    // every combination of two or more decorations needs its own rule,
+    // otherwise the classes would override each other
+    for mask in (1..1 << DECORATIONS.len()).filter(|mask: &u32| mask.count_ones() > 1) {
+        let active = DECORATIONS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, decoration)| decoration);
+
+        let (selector, lines) = active.fold(
+            (String::new(), Vec::new()),
+            |(mut selector, mut lines), &(format, line)| {
+                let _ = write!(selector, ".{CLASS_PREFIX}{}", format.name());
+                lines.push(line);
+                (selector, lines)
+            },
+        );
+
+        let _ = writeln!(
+            css,
+            "{selector} {{ {DECORATION_PROPERTY}: {}; }}",
+            lines.join(" ")
+        );
+    }
+
+    css
+}
+
+fn open_span(html: &mut String, style: &Style, classes: bool, theme: &Theme) {
+    let mut class_list = Vec::new();
+    let mut declarations = Vec::new();
+
+    // inverse swaps the colors, falling back to the page's default colors
+    let (fg, bg) = if style.has(Format::Inverse) {
+        let fg = style
+            .bg
+            .map_or(CssColor::Keyword("Canvas"), CssColor::Color);
+        let bg = style
+            .fg
+            .map_or(CssColor::Keyword("CanvasText"), CssColor::Color);
+        (Some(fg), Some(bg))
+    } else {
+        (style.fg.map(CssColor::Color), style.bg.map(CssColor::Color))
+    };
+
+    let ul = style.ul.map(CssColor::Color);
+
+    for (color, property, class) in [
+        (fg, "color", ""),
+        (bg, "background-color", "bg-"),
+        (ul, "text-decoration-color", "ul-"),
+    ] {
+        match color {
+            Some(CssColor::Color(Color::Palette(code))) if classes => {
+                class_list.push(format!("{CLASS_PREFIX}{class}{code}"));
+            }
+            Some(color) => declarations.push(format!("{property}:{}", color.css(theme))),
+            None => {}
+        }
+    }
+
+    if classes {
+        class_list.extend(
+            style
+                .formats()
+                .filter(|&format| format != Format::Inverse)
+                .map(|format| format!("{CLASS_PREFIX}{}", format.name())),
+        );
+        // the other underline styles only set the style of the line
+        if has_decoration(style, Format::Underline) && !style.has(Format::Underline) {
+            class_list.push(format!("{CLASS_PREFIX}{}", Format::Underline.name()));
+        }
+    } else {
+        declarations.extend(
+            style
+                .formats()
+                .filter_map(declaration)
+                .filter(|(property, _)| *property != DECORATION_PROPERTY)
+                .map(|(property, value)| format!("{property}:{value}")),
+        );
+
+        let lines: Vec<_> = DECORATIONS
+            .iter()
+            .filter(|(format, _)| has_decoration(style, *format))
+            .map(|(_, line)| *line)
+            .collect();
+
+        if !lines.is_empty() {
+            declarations.push(format!("{DECORATION_PROPERTY}:{}", lines.join(" ")));
+        }
+    }
+
+    html.push_str("<span");
+    if !class_list.is_empty() {
+        let _ = write!(html, " class=\"{}\"", class_list.join(" "));
+    }
+    if !declarations.is_empty() {
+        let _ = write!(html, " style=\"{}\"", declarations.join(";"));
+    }
+    html.push('>');
+}
+
+enum CssColor {
+    Color(Color),
+    Keyword(&'static str),
+}
+
+impl CssColor {
+    fn css(&self, theme: &Theme) -> String {
+        match self {
+            Self::Color(color) => color.css(theme),
+            Self::Keyword(keyword) => (*keyword).to_owned(),
+        }
+    }
+}
+
+fn declaration(format: Format) -> Option<(&'static str, &'static str)> {
+    match format {
+        Format::Hidden => Some(("visibility", "hidden")),
+        Format::Inverse => None,
+        Format::Dim => Some(("opacity", "0.5")),
+        Format::Blink => Some((DECORATION_PROPERTY, "blink")),
+        Format::Bold => Some(("font-weight", "bold")),
+        Format::Strikethrough => Some((DECORATION_PROPERTY, "line-through")),
+        Format::Underline => Some((DECORATION_PROPERTY, "underline")),
+        Format::Italic => Some(("font-style", "italic")),
+        Format::DoubleUnderline => Some(("text-decoration-style", "double")),
+        Format::CurlyUnderline => Some(("text-decoration-style", "wavy")),
+        Format::DottedUnderline => Some(("text-decoration-style", "dotted")),
+        Format::DashedUnderline => Some(("text-decoration-style", "dashed")),
+        Format::Overline => Some((DECORATION_PROPERTY, "overline")),
+    }
+}
+
+fn has_decoration(style: &Style, format: Format) -> bool {
+    if format == Format::Underline {
+        style.formats().any(Format::is_underline)
+    } else {
+        style.has(format)
+    }
+}
+
+fn escape_into(html: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            _ => html.push(ch),
+        }
+    }
+}
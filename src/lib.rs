@@ -9,7 +9,7 @@
 //! ```rust
 //! use dahlia::{Dahlia, dprintln};
 //!
-//! let dahlia = Dahlia::new().with_auto_depth();
+//! let dahlia = Dahlia::default().with_auto_depth();
 //!
 //! // Print "Hello, world!" in green bold with "world" underlined
 //! let formatted = dahlia.convert("&2&lHello, &nworld!");
@@ -22,7 +22,7 @@
 //! assert_eq!(dahlia.clean("&2Hello, &lworld!"), "Hello, world!");
 //!
 //! // Use `_` to escape the marker
-//! assert_eq!(dahlia.convert("&_2Hello!"), "&2Hello!");
+//! assert_eq!(dahlia.clean("&_2Hello!"), "&2Hello!");
 //! ```
 //!
 //! ## Specification
@@ -33,7 +33,8 @@
 //! ## Color Format Codes
 //!
 //! Each digit/letter corresponds to a hex value (dependent on the color depth). The coloring can
-//! be applied to the background if a `~` is inserted between the marker and the code.
+//! be applied to the background if a `~` is inserted between the marker and the code, or to
+//! the underline with a `^`.
 //!
 //! | Name       | Dahlia | ANSI 3-bit | ANSI 4-bit | ANSI 8-bit |       RGB       |    HEX    |
 //! | :--------- | :----: | :--------: | :--------: | :--------: | :-------------: | :------:  |
@@ -56,24 +57,35 @@
 //!
 //! ## Formatting Codes
 //!
-//! | Code | Result              |
-//! | ---- | ------------------- |
-//! | `l`  | Bold                |
-//! | `m`  | Strikethrough       |
-//! | `n`  | Underline           |
-//! | `o`  | Italic              |
-//! | `R`  | Reset formatting    |
-//! | `rf` | Reset foreground    |
-//! | `rb` | Reset background    |
-//! | `rc` | Reset color         |
-//! | `rh` | Reset hidden        |
-//! | `ri` | Reset inverse       |
-//! | `rj` | Reset dim           |
-//! | `rk` | Reset blinking      |
-//! | `rl` | Reset bold          |
-//! | `rm` | Reset strikethrough |
-//! | `rn` | Reset underline     |
-//! | `ro` | Reset italic        |
+//! | Code | Result                |
+//! | ---- | --------------------- |
+//! | `l`  | Bold                  |
+//! | `m`  | Strikethrough         |
+//! | `n`  | Underline             |
+//! | `o`  | Italic                |
+//! | `s`  | Double underline      |
+//! | `t`  | Curly underline       |
+//! | `u`  | Dotted underline      |
+//! | `v`  | Dashed underline      |
+//! | `w`  | Overline              |
+//! | `R`  | Reset formatting      |
+//! | `rf` | Reset foreground      |
+//! | `rb` | Reset background      |
+//! | `rc` | Reset color           |
+//! | `rh` | Reset hidden          |
+//! | `ri` | Reset inverse         |
+//! | `rj` | Reset dim             |
+//! | `rk` | Reset blinking        |
+//! | `rl` | Reset bold            |
+//! | `rm` | Reset strikethrough   |
+//! | `rn` | Reset underline       |
+//! | `ro` | Reset italic          |
+//! | `rs` | Reset underline       |
+//! | `rt` | Reset underline       |
+//! | `ru` | Reset underline       |
+//! | `rv` | Reset underline       |
+//! | `rw` | Reset overline        |
+//! | `r^` | Reset underline color |
 //!
 //! ## Custom Colors
 //!
@@ -81,15 +93,29 @@
 //!
 //! - Foreground: `&#xxx;` or `&#xxxxxx;`
 //! - Background: `&~#xxx;` or `&~#xxxxxx;`
+//! - Underline: `&^#xxx;` or `&^#xxxxxx;`
 //!
 //! `xxx` and `xxxxxx` represents the hex value of the color in 12/24 bits precision respectively.
+//!
+//! Colors of the xterm-256 palette can be picked by index with `&@n;` and `&~@n;`, where `n` is
+//! from 0 to 255. They are kept as indices at [`Depth::Medium`] and [`Depth::High`].
+//!
+//! Below [`Depth::High`], hex colors are downsampled to the nearest xterm-256 color
+//! ([`Depth::Medium`]) or the nearest palette color ([`Depth::Low`] and [`Depth::Tty`]).
+//! The metric used to find the nearest color can be chosen with [`Dahlia::with_color_distance`].
 use std::{
     borrow::Cow,
-    char, env,
+    char,
+    collections::HashMap,
+    fmt::{Display, Write as _},
     io::{stdin, stdout, Write},
+    iter,
+    sync::OnceLock,
 };
 
-use regex::{Captures, Regex};
+use memchr::memmem::Finder;
+use regex::Regex;
+use unicode_width::UnicodeWidthStr;
 
 #[cfg(test)]
 mod tests;
@@ -97,41 +123,149 @@ mod tests;
 mod consts;
 use consts::*;
 
+mod ansi;
+mod color;
+mod custom;
 mod depth;
+mod error;
+mod gradient;
+mod html;
+#[cfg(feature = "log")]
+mod logger;
+mod minecraft;
+mod parse;
+mod precompiled;
+mod preview;
+mod scan;
+mod spec;
+mod style;
+#[cfg(feature = "tracing")]
+mod subscriber;
+mod theme;
+mod wrap;
+mod writer;
 
+pub use ansi::ansi_to_dahlia;
+pub use color::ColorDistance;
+use color::{nearest_palette, nearest_xterm, xterm_rgb};
+use custom::Definition;
 pub use depth::Depth;
+pub use error::{DahliaError, InvalidCode, ThemeError};
+pub use html::html_stylesheet;
+#[cfg(feature = "log")]
+pub use logger::{DahliaLogger, LogStream};
+pub use minecraft::minecraft_legacy_to_dahlia;
+use parse::Item;
+pub use parse::{Format, Reset, Spanned, Token, Tokens};
+pub use precompiled::Precompiled;
+use scan::{Code, Scanner};
+pub use spec::{migrate_from_dahlia1, SpecVersion};
+use style::{Color, Style};
+#[cfg(feature = "tracing")]
+pub use subscriber::{DahliaFields, DahliaFormat};
+pub use theme::Theme;
+use wrap::{AnsiState, MarkupCode, MarkupState, Piece};
+pub use writer::DahliaWriter;
+
+const RESET: &str = "\x1b[0m";
+const LINK_END: &str = "\x1b]8;;\x1b\\";
 
+#[cfg(test)]
 const ESCAPE_IN_REGEX: [char; 14] = [
     '[', ']', '(', ')', '{', '}', '*', '+', '.', '$', '^', '\\', '|', '?',
 ];
 
+// the anchored code patterns of the instances without definitions, by spec
+// version and named colors, shared as they don't depend on the marker
+static SHARED_ANCHORED: [OnceLock<Regex>; 4] = [
+    OnceLock::new(),
+    OnceLock::new(),
+    OnceLock::new(),
+    OnceLock::new(),
+];
+
 struct Patterns {
-    codes: Regex,
-    escaped: String,
+    // every code, compiled on the first use of the regex switch
+    #[cfg(test)]
+    tokens: OnceLock<Regex>,
+    #[cfg(test)]
+    marker: char,
+    spec: SpecVersion,
+    named_colors: bool,
+    // the anchored code pattern with the definitions, compiled when first needed
+    anchored: OnceLock<Regex>,
+    finder: Finder<'static>,
 }
 
 impl Patterns {
-    pub fn new(marker: char) -> Self {
-        let escaped_marker = ESCAPE_IN_REGEX
-            .contains(&marker)
-            .then(|| format!(r"\{marker}"))
-            .unwrap_or_else(|| marker.to_string());
-
-        let regex = format!("{escaped_marker}(?:{})", *CODE_REGEX);
-
+    pub fn new(marker: char, spec: SpecVersion, named_colors: bool) -> Self {
         Self {
-            codes: Regex::new(&regex)
-                .expect("the pattern regex should be valid and properly escaped."),
-            escaped: format!("{marker}_"),
+            #[cfg(test)]
+            tokens: OnceLock::new(),
+            #[cfg(test)]
+            marker,
+            spec,
+            named_colors,
+            anchored: OnceLock::new(),
+            finder: Finder::new(marker.encode_utf8(&mut [0; 4]).as_bytes()).into_owned(),
         }
     }
 
-    pub fn codes(&self) -> &Regex {
-        &self.codes
+    fn code_regex(&self, definitions: &HashMap<String, Definition>) -> String {
+        let mut code_regex = match self.spec {
+            SpecVersion::Dahlia1 => DAHLIA1_CODE_REGEX.clone(),
+            SpecVersion::Dahlia2 => {
+                format!("{}|{}|{}", *LINK_REGEX, *GRADIENT_REGEX, *CODE_REGEX)
+            }
+        };
+        if self.named_colors {
+            code_regex = format!("{}|{code_regex}", *NAMED_COLOR_REGEX);
+        }
+        // defined codes take precedence over the built-in ones
+        if let Some(definitions) = custom::definitions_regex(definitions) {
+            code_regex = format!("{definitions}|{code_regex}");
+        }
+        code_regex
+    }
+
+    #[cfg(test)]
+    pub fn tokens(&self, definitions: &HashMap<String, Definition>) -> &Regex {
+        self.tokens.get_or_init(|| {
+            let marker = self.marker;
+            let escaped_marker = if ESCAPE_IN_REGEX.contains(&marker) {
+                format!(r"\{marker}")
+            } else {
+                marker.to_string()
+            };
+            let tokens = format!(
+                "{escaped_marker}(?:{}|(?<esc>_))",
+                self.code_regex(definitions)
+            );
+            Regex::new(&tokens).expect("the pattern regex should be valid and properly escaped.")
+        })
     }
 
-    pub fn escaped(&self) -> &str {
-        &self.escaped
+    /// The code pattern (without the marker) anchored at the start, for the
+    /// codes the scanner leaves to the regex.
+    ///
+    /// `definitions` have to be the ones the patterns were created for.
+    pub fn anchored(&self, definitions: &HashMap<String, Definition>) -> &Regex {
+        let regex = if definitions.is_empty() {
+            let index =
+                usize::from(self.spec == SpecVersion::Dahlia1) * 2 + usize::from(self.named_colors);
+            &SHARED_ANCHORED[index]
+        } else {
+            &self.anchored
+        };
+
+        regex.get_or_init(|| {
+            let anchored = format!(r"\A(?:{}|(?<esc>_))", self.code_regex(definitions));
+            Regex::new(&anchored).expect("the pattern regex should be valid and properly escaped.")
+        })
+    }
+
+    pub fn finder(&self) -> &Finder<'static> {
+        &self.finder
     }
 }
 
@@ -144,19 +278,40 @@ pub struct Dahlia {
     patterns: Patterns,
     // Marker used for formatting
     marker: char,
+    // Metric used when downsampling hex colors to lower depths
+    distance: ColorDistance,
+    // Set of format codes that is recognized
+    spec: SpecVersion,
+    // Whether colors can be referred to by name (`&[red]`)
+    named_colors: bool,
+    // Codes registered with `define` and `define_sgr`, by name
+    definitions: HashMap<String, Definition>,
+    // Colors of the palette codes
+    theme: Theme,
+    // Whether `clean` writes the URLs of links after their text
+    link_urls: bool,
+    // Whether codes are found with the code pattern alone, see `with_regex`
+    #[cfg(test)]
+    regex: bool,
 }
 
 impl Dahlia {
     pub fn new(depth: Option<Depth>, auto_reset: bool, marker: char) -> Self {
-        let no_color = env::var("NO_COLOR").is_ok_and(|value| !value.is_empty());
-
-        let depth = if no_color { None } else { depth };
+        let depth = if depth::no_color() { None } else { depth };
 
         Self {
             depth,
             auto_reset,
-            patterns: Patterns::new(marker),
+            patterns: Patterns::new(marker, SpecVersion::default(), false),
             marker,
+            distance: ColorDistance::default(),
+            spec: SpecVersion::default(),
+            named_colors: false,
+            definitions: HashMap::new(),
+            theme: Theme::default(),
+            link_urls: false,
+            #[cfg(test)]
+            regex: false,
         }
     }
 
@@ -180,6 +335,39 @@ impl Dahlia {
         self
     }
 
+    pub fn with_color_distance(mut self, distance: ColorDistance) -> Self {
+        self.set_color_distance(distance);
+        self
+    }
+
+    pub fn with_spec_version(mut self, spec: SpecVersion) -> Self {
+        self.set_spec_version(spec);
+        self
+    }
+
+    pub fn with_named_colors(mut self, named_colors: bool) -> Self {
+        self.set_named_colors(named_colors);
+        self
+    }
+
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.set_theme(theme);
+        self
+    }
+
+    pub fn with_link_urls(mut self, link_urls: bool) -> Self {
+        self.set_link_urls(link_urls);
+        self
+    }
+
+    // finds every code with the code pattern instead of the scanner, to
+    // compare them in the tests
+    #[cfg(test)]
+    pub(crate) fn with_regex(mut self) -> Self {
+        self.regex = true;
+        self
+    }
+
     pub fn set_depth(&mut self, depth: Depth) {
         self.depth = Some(depth);
     }
@@ -194,7 +382,245 @@ impl Dahlia {
 
     pub fn set_marker(&mut self, marker: char) {
         self.marker = marker;
-        self.patterns = Patterns::new(marker);
+        self.update_patterns();
+    }
+
+    pub fn set_color_distance(&mut self, distance: ColorDistance) {
+        self.distance = distance;
+    }
+
+    /// Sets which format codes are recognized, e.g. [`SpecVersion::Dahlia1`]
+    /// for strings written for Dahlia 1.x. The `_` escape works with both.
+    ///
+    /// Defined codes whose names would hide a built-in code of `spec` (like
+    /// `&p`, blink in Dahlia 1.x) are removed, see
+    /// [`try_set_spec_version`](Self::try_set_spec_version) for keeping them.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth, SpecVersion};
+    /// let mut dahlia = Dahlia::default().with_depth(Depth::Low);
+    /// dahlia.set_spec_version(SpecVersion::Dahlia1);
+    /// assert_eq!(dahlia.convert("&[#ff5555]&kx&r"), "\x1b[91m\x1b[8mx\x1b[0m");
+    /// ```
+    pub fn set_spec_version(&mut self, spec: SpecVersion) {
+        self.definitions
+            .retain(|name, _| custom::check_name(name, spec).is_ok());
+        self.spec = spec;
+        self.update_patterns();
+    }
+
+    /// Like [`set_spec_version`](Self::set_spec_version), but fails without
+    /// changing anything if the name of a defined code would hide a built-in
+    /// code of `spec`. The error lists these codes, at offset 0.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth, SpecVersion};
+    /// let mut dahlia = Dahlia::default().with_depth(Depth::Low);
+    /// dahlia.define("p", "&c").unwrap();
+    ///
+    /// let error = dahlia.try_set_spec_version(SpecVersion::Dahlia1).unwrap_err();
+    /// assert_eq!(error.codes[0].code, "&p");
+    /// assert_eq!(dahlia.convert("&px"), dahlia.convert("&cx"));
+    /// ```
+    pub fn try_set_spec_version(&mut self, spec: SpecVersion) -> Result<(), DahliaError> {
+        let mut names: Vec<_> = self.definitions.keys().collect();
+        names.sort();
+
+        let codes: Vec<_> = names
+            .into_iter()
+            .filter_map(|name| self.check_name(name, spec).err())
+            .flat_map(|error| error.codes)
+            .collect();
+
+        if !codes.is_empty() {
+            return Err(DahliaError { codes });
+        }
+
+        self.set_spec_version(spec);
+        Ok(())
+    }
+
+    /// Enables the `[name]` color syntax, e.g. `&[light_red]` or `&~[tomato]`.
+    ///
+    /// The names of the palette colors (see the README) map to their codes,
+    /// any other CSS color name is used as a hex color. Unknown names are
+    /// left as they are, like other invalid codes.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let mut dahlia = Dahlia::default().with_depth(Depth::Low).with_auto_reset(false);
+    /// dahlia.set_named_colors(true);
+    /// assert_eq!(dahlia.convert("&[light_red]!"), dahlia.convert("&c!"));
+    /// assert_eq!(dahlia.convert("&~[red]&[tomato]!"), "\x1b[41m\x1b[91m!");
+    /// ```
+    pub fn set_named_colors(&mut self, named_colors: bool) {
+        self.named_colors = named_colors;
+        self.update_patterns();
+    }
+
+    /// Sets the colors used for the palette codes, see [`Theme`].
+    ///
+    /// The theme applies to ANSI and HTML output, and to downsampling hex
+    /// colors at [`Depth::Low`] and [`Depth::Tty`].
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Sets whether [`clean`](Self::clean) (and [`convert`](Self::convert)
+    /// without a depth) writes links as `text (url)` instead of just their text.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let mut dahlia = Dahlia::default();
+    /// dahlia.set_link_urls(true);
+    /// assert_eq!(
+    ///     dahlia.clean("see &[link:https://docs.rs]the docs&[/link]"),
+    ///     "see the docs (https://docs.rs)"
+    /// );
+    /// ```
+    pub fn set_link_urls(&mut self, link_urls: bool) {
+        self.link_urls = link_urls;
+    }
+
+    /// Registers a code that expands to a combination of other codes.
+    ///
+    /// A single character name is used like the built-in codes (`&p`), longer
+    /// names in brackets (`&[error]`). Defined codes can use codes defined
+    /// before them. Defining a name again replaces the previous definition.
+    ///
+    /// Fails if `markup` contains invalid codes or anything else than codes,
+    /// or if the name can't be used. Names are 1 to 20 ASCII letters, digits,
+    /// `_` and `-`, and can't hide a built-in code: single characters can't be
+    /// or start one of the current [`SpecVersion`] (`&4`, `&r`), and longer
+    /// names can't be `rainbow` or a color name. The error for a name is
+    /// reported for the code as it would be written, at offset 0.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let mut dahlia = Dahlia::default().with_depth(Depth::Low);
+    /// dahlia.define("error", "&c&l").unwrap();
+    /// dahlia.define("p", "&[error]&n").unwrap();
+    ///
+    /// assert_eq!(dahlia.convert("&[error]oops"), dahlia.convert("&c&loops"));
+    /// assert_eq!(dahlia.convert("&poops"), dahlia.convert("&c&l&noops"));
+    /// assert!(dahlia.define("bad", "&cred").is_err());
+    /// assert!(dahlia.define("r", "&l").is_err());
+    /// ```
+    pub fn define(&mut self, name: &str, markup: &str) -> Result<(), DahliaError> {
+        self.check_name(name, self.spec)?;
+        self.validate(markup)?;
+
+        let mut definition = Definition::default();
+        let mut last_match = 0;
+
+        for (code, span) in self.scanner().codes(markup) {
+            if span.start > last_match || code == Code::Token(Token::Escape) {
+                // text (including escaped markers) can't be part of a definition
+                break;
+            }
+            last_match = span.end;
+
+            // gradients depend on the text following them, and links on the text
+            // being linked
+            let unsupported = match code {
+                Code::Gradient { .. } => Some("gradients"),
+                Code::Token(Token::Link(_) | Token::LinkEnd) => Some("links"),
+                _ => None,
+            };
+            if let Some(unsupported) = unsupported {
+                return Err(DahliaError {
+                    codes: vec![InvalidCode {
+                        offset: span.start,
+                        code: markup[span].to_owned(),
+                        suggestion: format!("definitions can't contain {unsupported}"),
+                    }],
+                });
+            }
+
+            match code {
+                Code::Defined(name) => {
+                    let nested = &self.definitions[name];
+                    definition.tokens.extend(&nested.tokens);
+                    definition.sgr.extend(nested.sgr.iter().cloned());
+                }
+                Code::Token(token) => definition.tokens.push(token.into_static()),
+                Code::Gradient { .. } => unreachable!("gradients can't be defined"),
+            }
+        }
+
+        if last_match < markup.len() {
+            return Err(DahliaError {
+                codes: vec![InvalidCode {
+                    offset: last_match,
+                    code: markup[last_match..].to_owned(),
+                    suggestion: "definitions can only contain codes".to_owned(),
+                }],
+            });
+        }
+
+        self.definitions.insert(name.to_owned(), definition);
+        self.update_patterns();
+        Ok(())
+    }
+
+    /// Registers a code that writes an SGR sequence with arbitrary parameters,
+    /// e.g. `"6"` for rapid blink.
+    ///
+    /// The sequence is only written by [`convert`](Self::convert) (and the
+    /// functions based on it), other outputs ignore the code. Names follow the
+    /// same rules as with [`define`](Self::define). Fails if the name can't be
+    /// used, or if `params` aren't numbers separated by `;` or `:` (the error
+    /// for the parameters is reported at offset 0).
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let mut dahlia = Dahlia::default().with_depth(Depth::Low).with_auto_reset(false);
+    /// dahlia.define_sgr("blink-fast", "6").unwrap();
+    /// assert_eq!(dahlia.convert("&[blink-fast]!"), "\x1b[6m!");
+    /// ```
+    pub fn define_sgr(&mut self, name: &str, params: &str) -> Result<(), DahliaError> {
+        self.check_name(name, self.spec)?;
+        custom::check_sgr(params).map_err(|suggestion| DahliaError {
+            codes: vec![InvalidCode {
+                offset: 0,
+                code: params.to_owned(),
+                suggestion,
+            }],
+        })?;
+
+        let definition = Definition {
+            tokens: Vec::new(),
+            sgr: vec![params.to_owned()],
+        };
+        self.definitions.insert(name.to_owned(), definition);
+        self.update_patterns();
+        Ok(())
+    }
+
+    /// Fails if `name` can't be used for a defined code with the codes of `spec`.
+    fn check_name(&self, name: &str, spec: SpecVersion) -> Result<(), DahliaError> {
+        custom::check_name(name, spec).map_err(|suggestion| {
+            let marker = self.marker;
+            let code = if name.chars().count() == 1 {
+                format!("{marker}{name}")
+            } else {
+                format!("{marker}[{name}]")
+            };
+
+            DahliaError {
+                codes: vec![InvalidCode {
+                    offset: 0,
+                    code,
+                    suggestion,
+                }],
+            }
+        })
     }
 
     /// Removes all Dahlia format codes from a string.
@@ -207,8 +633,34 @@ impl Dahlia {
     /// assert_eq!(dahlia.clean(green_text), ">be me");
     /// ```
     pub fn clean<'a>(&self, str: &'a str) -> Cow<'a, str> {
-        let converted = self.patterns.codes().replace_all(str, "");
-        self.finalize(converted)
+        let mut codes = self.scanner().codes(str).peekable();
+        if codes.peek().is_none() {
+            return Cow::Borrowed(str);
+        }
+
+        let mut cleaned = String::with_capacity(str.len());
+        let mut position = 0;
+        // the URL of the open link, written when it's closed
+        let mut url = None;
+
+        for (code, span) in codes {
+            cleaned.push_str(&str[position..span.start]);
+            position = span.end;
+
+            match code {
+                Code::Token(Token::Escape) => cleaned.push(self.marker),
+                Code::Token(Token::Link(link)) if self.link_urls => url = Some(link),
+                Code::Token(Token::LinkEnd) => {
+                    if let Some(url) = url.take() {
+                        let _ = write!(cleaned, " ({url})");
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        cleaned.push_str(&str[position..]);
+        Cow::Owned(cleaned)
     }
 
     /// Formats a string using the format codes.
@@ -221,48 +673,367 @@ impl Dahlia {
     /// assert_eq!(&text, "\x1b[38;2;85;255;85mHello \x1b[38;2;255;85;85mWorld\x1b[0m");
     /// ```
     pub fn convert<'a>(&self, str: &'a str) -> Cow<'a, str> {
-        if let Some(depth) = self.depth {
-            let replacer = |captures: &Captures<'_>| get_ansi(captures, depth);
-            let converted = self.patterns.codes().replace_all(str, replacer);
-            self.finalize(converted)
-        } else {
-            self.clean(str)
+        let converted = self.convert_without_reset(str);
+
+        match self.auto_reset_code() {
+            Some(reset) if !converted.ends_with(reset) => converted + reset,
+            _ => converted,
         }
     }
 
-    fn finalize<'a>(&self, str: Cow<'a, str>) -> Cow<'a, str> {
-        let str = if self.auto_reset && !str.ends_with("\x1b[0m") {
-            str + "\x1b[0m"
+    /// Like [`convert`](Self::convert), but fails if the string contains
+    /// markers that aren't part of a valid code (see [`validate`](Self::validate)).
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let dahlia = Dahlia::default().with_depth(Depth::Low);
+    /// assert_eq!(dahlia.try_convert("&4ok").unwrap(), "\x1b[31mok\x1b[0m");
+    ///
+    /// let error = dahlia.try_convert("&4&zoops").unwrap_err();
+    /// assert_eq!(error.codes[0].offset, 2);
+    /// ```
+    pub fn try_convert<'a>(&self, str: &'a str) -> Result<Cow<'a, str>, DahliaError> {
+        self.validate(str)?;
+        Ok(self.convert(str))
+    }
+
+    /// Checks that every marker in a string starts a valid code or an escape.
+    ///
+    /// The error lists all invalid codes, with their byte offsets and a
+    /// suggestion how to fix them.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// let error = dahlia.validate("&#fff &L").unwrap_err();
+    ///
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "invalid code `&#fff` at byte 0: hex colors end with `;`, did you mean `&#fff;`?\n\
+    ///      invalid code `&L` at byte 6: codes are case-sensitive, did you mean `&l`?"
+    /// );
+    /// ```
+    pub fn validate(&self, str: &str) -> Result<(), DahliaError> {
+        let codes: Vec<_> = self
+            .parse(str)
+            .filter_map(|Spanned { token, span }| match token {
+                Token::Text(text) => Some((span.start, text)),
+                _ => None,
+            })
+            .flat_map(|(start, text)| {
+                text.match_indices(self.marker).map(move |(i, marker)| {
+                    let offset = start + i;
+                    InvalidCode::new(offset, &str[offset + marker.len()..], self)
+                })
+            })
+            .collect();
+
+        if codes.is_empty() {
+            Ok(())
         } else {
-            str
+            Err(DahliaError { codes })
+        }
+    }
+
+    /// Returns the number of terminal columns a string takes up once converted.
+    ///
+    /// Codes take up no space, escaped markers count as a single marker, and
+    /// wide characters (CJK, emoji) as well as zero-width ones (combining marks)
+    /// are accounted for.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// assert_eq!(dahlia.display_width("&4&l日本&R &_"), 6);
+    /// ```
+    pub fn display_width(&self, str: &str) -> usize {
+        self.parse(str)
+            .map(|Spanned { token, .. }| match token {
+                Token::Text(text) => ansi_display_width(text),
+                Token::Escape => self.marker.encode_utf8(&mut [0; 4]).width(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Wraps a string to lines of at most `width` columns.
+    ///
+    /// Lines are broken at whitespace (words longer than a line are split).
+    /// The returned lines are Dahlia markup: each one starts with the codes
+    /// of the styling active at that point and ends with a full reset, so
+    /// they can be converted and printed independently. The codes are written
+    /// for the current [`SpecVersion`], with the closest Dahlia 1.x code (or
+    /// none) for codes Dahlia 1.x doesn't have. Defined codes are kept by
+    /// name, and those with SGR parameters (see [`define_sgr`](Self::define_sgr))
+    /// are written again on the following lines, until a full reset.
+    ///
+    /// Use [`wrap_ansi`] for already converted strings.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// assert_eq!(
+    ///     dahlia.wrap("&~4&lhello world&R again", 7),
+    ///     ["&~4&lhello&R", "&~4&lworld&R", "again"]
+    /// );
+    /// ```
+    pub fn wrap(&self, str: &str, width: usize) -> Vec<String> {
+        wrap::wrap(self.pieces(str), width, self.markup_state())
+    }
+
+    /// Cuts a string to at most `max_width` columns, ending it with `ellipsis`.
+    ///
+    /// Strings that already fit are returned unchanged. Otherwise the styling
+    /// up to the cut is kept, the ellipsis (which can contain codes of its own)
+    /// continues with the styling at the cut, and the result ends with a full
+    /// reset if any styling is active. The result is Dahlia markup, written
+    /// for the current [`SpecVersion`] like with [`wrap`](Self::wrap).
+    ///
+    /// Use [`truncate_ansi`] for already converted strings.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// assert_eq!(dahlia.truncate("&4&lvery long name", 8, "…"), "&4&lvery lo…&R");
+    /// assert_eq!(dahlia.truncate("&4long name", 6, "&8..."), "&4lon&8...&R");
+    /// assert_eq!(dahlia.truncate("&4short", 6, "…"), "&4short");
+    /// ```
+    pub fn truncate(&self, str: &str, max_width: usize, ellipsis: &str) -> String {
+        if self.display_width(str) <= max_width {
+            return str.to_owned();
+        }
+
+        wrap::truncate(
+            self.pieces(str),
+            self.pieces(ellipsis).collect(),
+            max_width,
+            self.markup_state(),
+        )
+    }
+
+    /// Splits a string into text and codes for laying it out.
+    fn pieces<'s>(&'s self, str: &'s str) -> impl Iterator<Item = Piece<'s, MarkupCode<'s>>> {
+        let mut tokens = self.parse(str).with_defined();
+
+        iter::from_fn(move || {
+            let piece = match tokens.next_item()? {
+                Item::Token(Spanned { token, span }) => match token {
+                    Token::Text(text) => Piece::Text(text),
+                    // an escape lays out as the marker itself
+                    Token::Escape => {
+                        Piece::Text(&str[span.start..span.start + self.marker.len_utf8()])
+                    }
+                    code => Piece::Code(MarkupCode::Token(code)),
+                },
+                // defined codes are kept, so that their SGR parameters aren't lost
+                Item::Defined(name, definition) => {
+                    Piece::Code(MarkupCode::Defined(name, definition))
+                }
+                Item::Sgr(_) => unreachable!("only convert reads the SGR parameters"),
+            };
+            Some(piece)
+        })
+    }
+
+    fn update_patterns(&mut self) {
+        self.patterns = Patterns::new(self.marker, self.spec, self.named_colors);
+    }
+
+    fn markup_state<'a>(&self) -> MarkupState<'a> {
+        MarkupState {
+            style: Style::default(),
+            marker: self.marker,
+            spec: self.spec,
+            link: None,
+            defined: Vec::new(),
+        }
+    }
+
+    /// Converts the format codes without appending the automatic reset.
+    pub(crate) fn convert_without_reset<'a>(&self, str: &'a str) -> Cow<'a, str> {
+        let Some(depth) = self.depth else {
+            return self.clean(str);
         };
+        if !str.contains(self.marker) {
+            return Cow::Borrowed(str);
+        }
 
-        self.unescape(str)
+        let mut converted = String::with_capacity(str.len() * 2);
+        let mut position = 0;
+
+        for (code, span) in self.scanner().codes(str) {
+            converted.push_str(&str[position..span.start]);
+            position = span.end;
+
+            match code {
+                Code::Token(Token::Escape) => converted.push(self.marker),
+                Code::Token(token) => {
+                    push_ansi(&mut converted, token, depth, self.distance, &self.theme)
+                }
+                Code::Defined(name) => {
+                    let definition = &self.definitions[name];
+                    for &token in &definition.tokens {
+                        push_ansi(&mut converted, token, depth, self.distance, &self.theme);
+                    }
+                    for params in &definition.sgr {
+                        push_sgr(&mut converted, params);
+                    }
+                }
+                // the colors of a gradient depend on the text following it, the
+                // rest is converted from the parsed tokens
+                Code::Gradient { .. } => {
+                    self.convert_tokens(&str[span.start..], depth, &mut converted);
+                    return Cow::Owned(converted);
+                }
+            }
+        }
+
+        converted.push_str(&str[position..]);
+        Cow::Owned(converted)
     }
 
-    fn unescape<'a>(&self, str: Cow<'a, str>) -> Cow<'a, str> {
-        // PERF: Custom String::replace implementation based on Regex::replace_all to get
-        // around 2x speed boost
-        let mut indices = str.match_indices(self.patterns.escaped()).peekable();
+    /// Converts the format codes of the parsed tokens, including the colors
+    /// of gradients.
+    fn convert_tokens(&self, str: &str, depth: Depth, converted: &mut String) {
+        let mut tokens = self.parse(str).with_sgr();
 
-        if indices.peek().is_none() {
-            return str;
+        while let Some(item) = tokens.next_item() {
+            match item {
+                Item::Token(Spanned { token, .. }) => match token {
+                    Token::Text(text) => converted.push_str(text),
+                    Token::Escape => converted.push(self.marker),
+                    token => push_ansi(converted, token, depth, self.distance, &self.theme),
+                },
+                Item::Sgr(params) => push_sgr(converted, params),
+                Item::Defined(..) => unreachable!("defined codes are expanded when converting"),
+            }
         }
+    }
 
-        let buffer = String::with_capacity(str.len());
+    /// Finds the codes of a string.
+    pub(crate) fn scanner(&self) -> Scanner<'_> {
+        Scanner {
+            marker: self.marker,
+            spec: self.spec,
+            definitions: &self.definitions,
+            patterns: &self.patterns,
+            #[cfg(test)]
+            regex: self.regex.then(|| self.patterns.tokens(&self.definitions)),
+        }
+    }
 
-        let (new, last_match) = indices.fold((buffer, 0), |(acc, last_match), (start, chunk)| {
-            (
-                // accumulator + string since the previous match + the match without the _
-                acc + &str[last_match..start] + &chunk[..chunk.len() - 1],
-                start + chunk.len(),
-            )
-        });
+    /// The reset appended by `convert`, if auto reset applies.
+    pub(crate) fn auto_reset_code(&self) -> Option<&'static str> {
+        (self.auto_reset && self.depth.is_some()).then_some(RESET)
+    }
 
-        // rest of the string
-        let tail = &str[last_match..];
+    /// Splits a string into a sequence of tokens with their byte spans.
+    ///
+    /// Text between codes is yielded as [`Token::Text`], escaped markers
+    /// as [`Token::Escape`].
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Format, Spanned, Token};
+    /// let dahlia = Dahlia::default();
+    /// let tokens: Vec<_> = dahlia.parse("&~4&lHi&_").collect();
+    /// assert_eq!(
+    ///     tokens,
+    ///     [
+    ///         Spanned { token: Token::Color { code: '4', background: true }, span: 0..3 },
+    ///         Spanned { token: Token::Format(Format::Bold), span: 3..5 },
+    ///         Spanned { token: Token::Text("Hi"), span: 5..7 },
+    ///         Spanned { token: Token::Escape, span: 7..9 },
+    ///     ]
+    /// );
+    /// ```
+    pub fn parse<'a>(&self, str: &'a str) -> Tokens<'_, 'a> {
+        Tokens::new(str, self.scanner())
+    }
+
+    /// Renders a string as HTML, using `<span>` elements with inline styles.
+    ///
+    /// Colors are always rendered in 24-bit, regardless of the depth.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// let html = dahlia.to_html("&a&lok&R <done>");
+    /// assert_eq!(html, "<span style=\"color:#55ff55;font-weight:bold\">ok</span> &lt;done&gt;");
+    /// ```
+    pub fn to_html(&self, str: &str) -> String {
+        html::render(self.parse(str), self.marker, false, &self.theme)
+    }
+
+    /// Renders a string as HTML, using `<span>` elements with CSS classes.
+    ///
+    /// Palette colors and formats are rendered as `dahlia-*` classes (see
+    /// [`html_stylesheet`]), hex colors as inline styles.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// let html = dahlia.to_html_classes("&~4&nalert");
+    /// assert_eq!(html, "<span class=\"dahlia-bg-4 dahlia-underline\">alert</span>");
+    /// ```
+    pub fn to_html_classes(&self, str: &str) -> String {
+        html::render(self.parse(str), self.marker, true, &self.theme)
+    }
+
+    /// Renders a string as a Minecraft JSON text component.
+    ///
+    /// Palette colors are rendered as Minecraft's named colors and hex colors
+    /// as `#rrggbb`. Minecraft has no background colors, and no equivalent of
+    /// the hidden, inverse and dim formats, so those are dropped. Blink is
+    /// rendered as `obfuscated`, which is what `§k` means in Minecraft.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// assert_eq!(
+    ///     dahlia.to_minecraft_json("&6&lGold&R and &#ff00ff;pink"),
+    ///     r##"{"text":"","extra":[{"text":"Gold","color":"gold","bold":true},{"text":" and "},{"text":"pink","color":"#ff00ff"}]}"##
+    /// );
+    /// ```
+    pub fn to_minecraft_json(&self, str: &str) -> String {
+        minecraft::render(self.parse(str), self.marker)
+    }
 
-        Cow::Owned(new + tail)
+    /// Renders a string with Minecraft's legacy `§` codes.
+    ///
+    /// Hex colors use the `§x§r§r§g§g§b§b` form. Like
+    /// [`to_minecraft_json`](Dahlia::to_minecraft_json), background colors and
+    /// the hidden, inverse and dim formats are dropped and blink becomes `§k`
+    /// (obfuscated). As color codes reset the formats in Minecraft, the formats
+    /// are repeated after every color, and turning off a format is done with
+    /// a color code or `§r`.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Dahlia;
+    /// let dahlia = Dahlia::default();
+    /// assert_eq!(
+    ///     dahlia.to_minecraft_legacy("&l&6Gold&rl, &#ff00ff;pink&R!"),
+    ///     "§6§lGold§6, §x§f§f§0§0§f§fpink§r!"
+    /// );
+    /// ```
+    pub fn to_minecraft_legacy(&self, str: &str) -> String {
+        minecraft::render_legacy(self.parse(str), self.marker)
+    }
+
+    /// Wraps a writer, converting the format codes written to it.
+    ///
+    /// See [`DahliaWriter`] for details.
+    pub fn writer<W: Write>(&self, inner: W) -> DahliaWriter<'_, W> {
+        DahliaWriter::new(self, inner)
     }
 
     /// Writes the prompt to stdout, then reads a line from input,
@@ -286,87 +1057,173 @@ impl Dahlia {
     /// assert_eq!(str, "&_aHello &_cWorld");
     /// ```
     pub fn escape(&self, str: &str) -> String {
-        str.replace(self.marker, self.patterns.escaped())
+        escape_markers(str, self.marker)
     }
-}
 
-fn get_ansi(captures: &Captures<'_>, depth: Depth) -> String {
-    if let Some(format) = captures.name("fmt") {
-        return format_to_ansi(format.as_str());
+    /// Returns a preview of the colors, formats and resets, for checking what
+    /// a terminal supports.
+    ///
+    /// The palette and sample gradients are shown at every depth, next to the
+    /// depth detected by [`Depth::try_infer`] and the variables it is based
+    /// on, and the formats and resets at the depth of this `Dahlia`. The
+    /// colors of its theme are used, but not its marker or custom codes.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Dahlia, Depth};
+    /// let dahlia = Dahlia::default().with_depth(Depth::High);
+    /// print!("{}", dahlia.preview());
+    /// ```
+    pub fn preview(&self) -> String {
+        preview::render(self)
     }
+}
 
-    let bg = captures.name("bg").is_some();
-
-    let templater = if bg {
-        fmt_background_template
-    } else {
-        fmt_template
-    };
+/// Escapes the markers of `text`, so that it is read as plain text.
+pub(crate) fn escape_markers(text: &str, marker: char) -> String {
+    text.replace(marker, &Token::Escape.to_markup(marker))
+}
 
-    if let Some(hex) = captures.name("hex") {
-        return hex_to_ansi(hex.as_str(), templater);
+/// Writes the ANSI escape code of a token to `out`.
+fn push_ansi(
+    out: &mut String,
+    token: Token<'_>,
+    depth: Depth,
+    distance: ColorDistance,
+    theme: &Theme,
+) {
+    match token {
+        // the Linux console only knows the single underline
+        Token::Format(format) if format.is_underline() && depth == Depth::Tty => {
+            push_format(out, Format::Underline.code());
+        }
+        Token::Format(format) => push_format(out, format.code()),
+        Token::Reset(reset) => push_format(out, reset.code()),
+        Token::Hex { rgb, background } => {
+            push_hex(out, rgb, background, depth, distance, theme);
+        }
+        Token::Color { code, background } => push_color(out, code, background, depth, theme),
+        Token::Xterm { index, background } => {
+            push_xterm(out, index, background, depth, distance, theme);
+        }
+        Token::UnderlineColor { code } => {
+            push_underline(out, Color::Palette(code), depth, distance, theme);
+        }
+        Token::UnderlineHex { rgb } => push_underline(out, Color::Rgb(rgb), depth, distance, theme),
+        Token::UnderlineXterm { index } => {
+            push_underline(out, Color::Xterm(index), depth, distance, theme);
+        }
+        Token::Link(url) => {
+            let _ = write!(out, "\x1b]8;;{url}\x1b\\");
+        }
+        Token::LinkEnd => out.push_str(LINK_END),
+        Token::Text(_) | Token::Escape => unreachable!("only format codes are converted to ANSI"),
     }
+}
 
-    // if it's not a formatter or hex code, it's a color code
-    let color = &captures["color"];
+// the first SGR parameter of an extended foreground, background or underline color
+fn selector(bg: bool) -> u8 {
+    if bg {
+        48
+    } else {
+        38
+    }
+}
 
-    if depth == Depth::High {
-        let [r, g, b] = COLORS_24BIT(color).expect("the regex should match only valid color codes");
+fn push_sgr(out: &mut String, value: impl Display) {
+    let _ = write!(out, "\x1b[{value}m");
+}
 
-        return fill_rgb_template(templater(Depth::High), r, g, b);
-    }
+fn push_indexed(out: &mut String, selector: u8, index: u8) {
+    let _ = write!(out, "\x1b[{selector};5;{index}m");
+}
 
-    let color_map = colors(depth).expect("at this point depth should only be TTY, Low or Medium");
+fn push_rgb(out: &mut String, selector: u8, [r, g, b]: [u8; 3]) {
+    let _ = write!(out, "\x1b[{selector};2;{r};{g};{b}m");
+}
 
-    let mapped = color_map(color).expect("the regex should match only valid color codes");
+fn push_color(out: &mut String, code: char, bg: bool, depth: Depth, theme: &Theme) {
+    let index = scan::color_index(code);
 
-    // low bit depths use different way of specifying background
-    let value = if bg && depth <= Depth::Low {
-        (mapped
-            .parse::<u8>()
-            .expect("color tables should contain valid numbers")
-            + 10)
-            .to_string()
-    } else {
-        mapped.to_string()
+    let value = match depth {
+        Depth::High => return push_rgb(out, selector(bg), theme.rgb[index]),
+        Depth::Medium => return push_indexed(out, selector(bg), theme.xterm[index]),
+        Depth::Low => theme.ansi[index],
+        Depth::Tty => theme.tty[index],
     };
 
-    fill_template(templater(depth), &value)
+    // low bit depths use different way of specifying background
+    push_sgr(out, if bg { value + 10 } else { value });
 }
 
-fn hex_to_ansi(hex: &str, templater: fn(Depth) -> &'static str) -> String {
-    let hex_digits = hex
-        .chars()
-        .map(|ch| ch.to_digit(16))
-        .collect::<Option<Vec<_>>>()
-        .expect("the regex should only match valid hexadecimal digits");
-
-    let [r, g, b] = match &hex_digits[..] {
-        // if there are only 3 digits, "duplicate" each
-        [r, g, b] => [r, g, b].map(|&d| (0x11 * d).to_string()),
-        [r1, r2, g1, g2, b1, b2] => {
-            [(r1, r2), (g1, g2), (b1, b2)].map(|(h, l)| (h * 0x10 + l).to_string())
+fn push_hex(
+    out: &mut String,
+    rgb: [u8; 3],
+    bg: bool,
+    depth: Depth,
+    distance: ColorDistance,
+    theme: &Theme,
+) {
+    match depth {
+        Depth::High => push_rgb(out, selector(bg), rgb),
+        Depth::Medium => push_indexed(out, selector(bg), nearest_xterm(rgb, distance)),
+        Depth::Low | Depth::Tty => {
+            let code = nearest_palette(rgb, depth == Depth::Tty, distance, &theme.rgb);
+            push_color(out, code, bg, depth, theme);
         }
-        _ => unreachable!("the regex should only match codes of length 3 or 6"),
-    };
+    }
+}
+
+fn push_xterm(
+    out: &mut String,
+    index: u8,
+    bg: bool,
+    depth: Depth,
+    distance: ColorDistance,
+    theme: &Theme,
+) {
+    match depth {
+        // the index is kept as is, so the color matches the terminal's palette
+        Depth::High | Depth::Medium => push_indexed(out, selector(bg), index),
+        // the system colors are the basic colors
+        Depth::Low | Depth::Tty if index < 16 => {
+            let value = match index {
+                0..=7 => 30 + index,
+                _ if depth == Depth::Tty => 30 + index - 8,
+                _ => 90 + index - 8,
+            };
 
-    fill_rgb_template(templater(Depth::High), &r, &g, &b)
+            push_sgr(out, if bg { value + 10 } else { value });
+        }
+        Depth::Low | Depth::Tty => push_hex(out, xterm_rgb(index), bg, depth, distance, theme),
+    }
 }
 
-fn format_to_ansi(format: &str) -> String {
-    use std::fmt::Write;
+fn push_underline(
+    out: &mut String,
+    color: Color,
+    depth: Depth,
+    distance: ColorDistance,
+    theme: &Theme,
+) {
+    let index = match (color, depth) {
+        // 16-color terminals have no way of coloring underlines
+        (_, Depth::Tty | Depth::Low) => return,
+        (Color::Xterm(index), _) => index,
+        (_, Depth::High) => return push_rgb(out, 58, color.rgb(theme)),
+        (Color::Palette(code), _) => theme.xterm[scan::color_index(code)],
+        (Color::Rgb(rgb), _) => nearest_xterm(rgb, distance),
+    };
+    push_indexed(out, 58, index);
+}
 
+fn push_format(out: &mut String, format: &str) {
     let ansis = formatter(format)
         .expect("the regex should match only valid formatter codes or reset codes.");
 
-    ansis.iter().fold(
-        String::with_capacity(ansis.len() * 5), // ansi format codes are 5 chars long
-        |mut string, ansi| {
-            // writing to string can't fail, and we use it, so we get the format! capability
-            let _ = write!(string, "\x1b[{ansi}m");
-            string
-        },
-    )
+    for ansi in ansis {
+        push_sgr(out, ansi);
+    }
 }
 
 impl Default for Dahlia {
@@ -375,17 +1232,6 @@ impl Default for Dahlia {
     }
 }
 
-fn fill_template(template: &str, value: &str) -> String {
-    template.replacen("{}", value, 1)
-}
-
-fn fill_rgb_template(template: &str, r: &str, g: &str, b: &str) -> String {
-    template
-        .replacen("{r}", r, 1)
-        .replacen("{g}", g, 1)
-        .replacen("{b}", b, 1)
-}
-
 /// Removes all ANSI codes from a string.
 ///
 /// # Example
@@ -403,6 +1249,65 @@ pub fn clean_ansi(string: &str) -> Cow<'_, str> {
     ANSI_REGEX.replace_all(string, "")
 }
 
+/// Returns the number of terminal columns a string takes up, ignoring ANSI codes.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::ansi_display_width;
+/// assert_eq!(ansi_display_width("\x1b[31m👍 e\u{301}\x1b[0m"), 4);
+/// ```
+pub fn ansi_display_width(string: &str) -> usize {
+    clean_ansi(string).width()
+}
+
+/// Wraps a string with ANSI codes to lines of at most `width` columns.
+///
+/// Lines are broken at whitespace (words longer than a line are split).
+/// Each line repeats the SGR sequences active at its start, and lines
+/// with active styling end with a reset.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::wrap_ansi;
+/// assert_eq!(
+///     wrap_ansi("\x1b[41mred alert\x1b[0m!", 6),
+///     ["\x1b[41mred\x1b[0m", "\x1b[41malert\x1b[0m!"]
+/// );
+/// ```
+pub fn wrap_ansi(string: &str, width: usize) -> Vec<String> {
+    wrap::wrap(wrap::ansi_pieces(string), width, AnsiState::default())
+}
+
+/// Cuts a string with ANSI codes to at most `max_width` columns, ending it with `ellipsis`.
+///
+/// Strings that already fit are returned unchanged. Otherwise the ANSI codes
+/// up to the cut are kept, the ellipsis continues with the styling at the cut,
+/// and the result ends with a reset if any styling is active.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::truncate_ansi;
+/// assert_eq!(
+///     truncate_ansi("\x1b[31mlong name\x1b[0m", 5, "…"),
+///     "\x1b[31mlong…\x1b[0m"
+/// );
+/// ```
+pub fn truncate_ansi(string: &str, max_width: usize, ellipsis: &str) -> String {
+    if ansi_display_width(string) <= max_width {
+        return string.to_owned();
+    }
+
+    wrap::truncate(
+        wrap::ansi_pieces(string),
+        wrap::ansi_pieces(ellipsis),
+        max_width,
+        AnsiState::default(),
+    )
+}
+
 /// Wrapper over `print!`, takes a Dahlia instance as the first argument
 /// and uses its convert method for coloring strings.
 ///
@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::{depth, Dahlia, Depth};
+
+/// The stream a [`DahliaLogger`] writes to.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum LogStream {
+    /// Standard output
+    Stdout,
+    /// Standard error, the default
+    #[default]
+    Stderr,
+}
+
+/// A [`log::Log`] implementation that writes records with Dahlia codes,
+/// available with the `log` feature.
+///
+/// Every record is written as its level prefix followed by the message, and
+/// the whole line is converted, so messages can contain codes too. The color
+/// depth is inferred for the stream the logger writes to (see
+/// [`Depth::try_infer_for`]), so colors are left out when it is redirected
+/// or `NO_COLOR` is set.
+///
+/// ### Example
+/// ```rust
+/// # use dahlia::DahliaLogger;
+/// use log::{Level, LevelFilter};
+///
+/// DahliaLogger::new()
+///     .with_level(LevelFilter::Debug)
+///     .with_template(Level::Info, "&9&l[info]&R")
+///     .init()
+///     .unwrap();
+///
+/// log::info!("server started on &lport 8080");
+/// ```
+pub struct DahliaLogger {
+    dahlia: Dahlia,
+    level: LevelFilter,
+    // level prefixes, indexed by `Level as usize - 1`
+    templates: [String; 5],
+    stream: LogStream,
+    // explicitly set depth, inferred for the stream if `None`
+    depth: Option<Depth>,
+}
+
+impl Default for DahliaLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DahliaLogger {
+    /// Creates a logger writing records of level `Info` and above to stderr.
+    pub fn new() -> Self {
+        let mut logger = Self {
+            dahlia: Dahlia::new(None, true, '&'),
+            level: LevelFilter::Info,
+            templates: [
+                "&c&l[ERROR]&R",
+                "&e&l[WARN]&R",
+                "&a[INFO]&R",
+                "&b[DEBUG]&R",
+                "&8[TRACE]&R",
+            ]
+            .map(str::to_owned),
+            stream: LogStream::default(),
+            depth: None,
+        };
+        logger.update_depth();
+        logger
+    }
+
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.set_level(level);
+        self
+    }
+
+    pub fn with_template(mut self, level: Level, template: impl Into<String>) -> Self {
+        self.set_template(level, template);
+        self
+    }
+
+    pub fn with_stream(mut self, stream: LogStream) -> Self {
+        self.set_stream(stream);
+        self
+    }
+
+    pub fn with_depth(mut self, depth: Depth) -> Self {
+        self.set_depth(depth);
+        self
+    }
+
+    pub fn with_dahlia(mut self, dahlia: Dahlia) -> Self {
+        self.set_dahlia(dahlia);
+        self
+    }
+
+    /// Sets the most verbose level that is logged.
+    pub fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+    }
+
+    /// Sets the prefix of the records of a level, written with Dahlia codes.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{DahliaLogger, Depth};
+    /// # use log::{Level, Record};
+    /// let mut logger = DahliaLogger::new().with_depth(Depth::Low);
+    /// logger.set_template(Level::Warn, "&6warning:&R");
+    ///
+    /// let record = Record::builder()
+    ///     .level(Level::Warn)
+    ///     .args(format_args!("disk almost full"))
+    ///     .build();
+    /// assert_eq!(logger.format(&record), "\x1b[33mwarning:\x1b[0m disk almost full\x1b[0m");
+    /// ```
+    pub fn set_template(&mut self, level: Level, template: impl Into<String>) {
+        self.templates[level as usize - 1] = template.into();
+    }
+
+    /// Sets the stream records are written to, and infers the color depth
+    /// for it unless it was set with [`set_depth`](Self::set_depth).
+    pub fn set_stream(&mut self, stream: LogStream) {
+        self.stream = stream;
+        self.update_depth();
+    }
+
+    /// Sets the color depth instead of inferring it for the stream.
+    /// `NO_COLOR` still turns the colors off.
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = Some(depth);
+        self.update_depth();
+    }
+
+    /// Sets the `Dahlia` used to convert the records, e.g. for a different
+    /// marker, theme or custom codes. Its color depth is replaced by the one
+    /// of the logger.
+    pub fn set_dahlia(&mut self, dahlia: Dahlia) {
+        self.dahlia = dahlia;
+        self.update_depth();
+    }
+
+    /// Installs the logger as the global logger and sets the maximum level
+    /// of the `log` macros to the level of the logger.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Returns a record as it is written, without the trailing newline.
+    pub fn format(&self, record: &Record<'_>) -> String {
+        let template = &self.templates[record.level() as usize - 1];
+        self.dahlia
+            .convert(&format!("{template} {}", record.args()))
+            .into_owned()
+    }
+
+    fn update_depth(&mut self) {
+        self.dahlia.depth = match (self.depth, self.stream) {
+            (Some(_), _) if depth::no_color() => None,
+            (Some(depth), _) => Some(depth),
+            (None, LogStream::Stdout) => Depth::try_infer_for(&io::stdout()),
+            (None, LogStream::Stderr) => Depth::try_infer_for(&io::stderr()),
+        };
+    }
+}
+
+impl Log for DahliaLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(record);
+        // a logger has nowhere to report its own errors
+        let _ = match self.stream {
+            LogStream::Stdout => writeln!(io::stdout().lock(), "{line}"),
+            LogStream::Stderr => writeln!(io::stderr().lock(), "{line}"),
+        };
+    }
+
+    fn flush(&self) {
+        let _ = match self.stream {
+            LogStream::Stdout => io::stdout().flush(),
+            LogStream::Stderr => io::stderr().flush(),
+        };
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt::Write;
+
+use crate::{
+    color::xterm_rgb,
+    escape_markers,
+    style::{css_hex, Color, Style},
+    Format, Reset, Spanned, Token,
+};
+
+const SECTION: char = '§';
+
+// formats with a Minecraft equivalent, in the order their keys are written
+const FORMATS: [(Format, &str); 5] = [
+    (Format::Bold, "bold"),
+    (Format::Italic, "italic"),
+    (Format::Underline, "underlined"),
+    (Format::Strikethrough, "strikethrough"),
+    // `&k` is blink in Dahlia, but `§k` is obfuscated in Minecraft
+    (Format::Blink, "obfuscated"),
+];
+
+/// Renders parsed Dahlia tokens as a Minecraft JSON text component.
+pub(crate) fn render<'a>(tokens: impl Iterator<Item = Spanned<'a>>, marker: char) -> String {
+    let mut runs: Vec<(Run<'a>, String)> = Vec::new();
+    let mut style = Style::default();
+    let mut link = None;
+
+    for Spanned { token, .. } in tokens {
+        let mut marker_buffer = [0; 4];
+
+        let text = match token {
+            Token::Text(text) => text,
+            Token::Escape => marker.encode_utf8(&mut marker_buffer),
+            Token::Link(url) => {
+                link = Some(url);
+                continue;
+            }
+            Token::LinkEnd => {
+                link = None;
+                continue;
+            }
+            _ => {
+                style.apply(&token);
+                continue;
+            }
+        };
+
+        // only the parts of the style that Minecraft can show tell runs apart
+        let visible = Run {
+            style: visible(&style),
+            link,
+        };
+        match runs.last_mut() {
+            Some((last, run)) if *last == visible => run.push_str(text),
+            _ => runs.push((visible, text.to_owned())),
+        }
+    }
+
+    match &runs[..] {
+        [] => component(&Run::default(), ""),
+        [(run, text)] => component(run, text),
+        _ => {
+            let extra: Vec<_> = runs
+                .iter()
+                .map(|(run, text)| component(run, text))
+                .collect();
+            format!("{{\"text\":\"\",\"extra\":[{}]}}", extra.join(","))
+        }
+    }
+}
+
+/// The parts of the styling that a JSON text component can show.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Run<'a> {
+    style: Style,
+    link: Option<&'a str>,
+}
+
+/// Renders parsed Dahlia tokens as a legacy `§`-coded Minecraft string.
+pub(crate) fn render_legacy<'a>(tokens: impl Iterator<Item = Spanned<'a>>, marker: char) -> String {
+    let mut legacy = String::new();
+    let mut style = Style::default();
+    // style of the codes written so far
+    let mut current = Style::default();
+
+    for Spanned { token, .. } in tokens {
+        let mut marker_buffer = [0; 4];
+
+        let text = match token {
+            Token::Text(text) => text,
+            Token::Escape => marker.encode_utf8(&mut marker_buffer),
+            _ => {
+                style.apply(&token);
+                continue;
+            }
+        };
+
+        let visible = visible(&style);
+        if visible != current {
+            // in Minecraft, color codes also reset the formats, so a format
+            // can only be turned off with a color code or `§r`
+            let reset = visible.fg != current.fg || current.formats().any(|f| !visible.has(f));
+
+            if reset {
+                match visible.fg {
+                    Some(Color::Palette(code)) => push_legacy_code(&mut legacy, code),
+                    Some(Color::Rgb(rgb)) => {
+                        push_legacy_code(&mut legacy, 'x');
+                        for digit in css_hex(rgb)[1..].chars() {
+                            push_legacy_code(&mut legacy, digit);
+                        }
+                    }
+                    None => push_legacy_code(&mut legacy, 'r'),
+                    Some(Color::Xterm(_)) => unreachable!("`visible` converts xterm colors to hex"),
+                }
+            }
+
+            for format in visible.formats() {
+                if reset || !current.has(format) {
+                    // the letters are the same, `&k` (blink) becomes `§k` (obfuscated)
+                    legacy.push(SECTION);
+                    legacy.push_str(format.code());
+                }
+            }
+
+            current = visible;
+        }
+
+        legacy.push_str(text);
+    }
+
+    legacy
+}
+
+/// Converts a legacy `§`-coded Minecraft string to Dahlia codes.
+///
+/// Colors, formats and `§r` are translated, as well as hex colors in the
+/// `§x§r§r§g§g§b§b` form. As color codes reset the formats in Minecraft, a
+/// `R` reset is added before colors that follow formats. `§k` (obfuscated)
+/// becomes `k` (blink). Unknown codes are kept as text, and markers already
+/// present in the text are escaped.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::minecraft_legacy_to_dahlia;
+/// let legacy = "§6§lGold§a & §x§f§f§0§0§f§fpink";
+/// assert_eq!(
+///     minecraft_legacy_to_dahlia(legacy, '&'),
+///     "&6&lGold&R&a &_ &#ff00ff;pink"
+/// );
+/// ```
+pub fn minecraft_legacy_to_dahlia(string: &str, marker: char) -> String {
+    let mut dahlia = String::with_capacity(string.len());
+    // whether a format is active, which the next color code resets
+    let mut formatted = false;
+    let mut rest = string;
+
+    while let Some(index) = rest.find(SECTION) {
+        dahlia.push_str(&escape_markers(&rest[..index], marker));
+        let code_start = &rest[index..];
+        rest = &code_start[SECTION.len_utf8()..];
+
+        let Some(code) = rest.chars().next() else {
+            rest = code_start;
+            break;
+        };
+        let code = code.to_ascii_lowercase();
+        let mut code_len = code.len_utf8();
+
+        let token = match code {
+            '0'..='9' | 'a'..='f' => Some(Token::Color {
+                code,
+                background: false,
+            }),
+            'x' => bungee_hex(&rest[1..]).map(|rgb| {
+                code_len += 6 * (SECTION.len_utf8() + 1);
+                Token::Hex {
+                    rgb,
+                    background: false,
+                }
+            }),
+            'k'..='o' => Format::from_code(&code.to_string()).map(Token::Format),
+            'r' => Some(Token::Reset(Reset::All)),
+            _ => None,
+        };
+
+        let Some(token) = token else {
+            // unknown codes are kept as they are, escaped if `§` is the marker
+            if marker == SECTION {
+                dahlia.push_str(&Token::Escape.to_markup(marker));
+            } else {
+                dahlia.push(SECTION);
+            }
+            continue;
+        };
+
+        match token {
+            Token::Color { .. } | Token::Hex { .. } if formatted => {
+                dahlia.push_str(&Token::Reset(Reset::All).to_markup(marker));
+            }
+            _ => {}
+        }
+        formatted = matches!(token, Token::Format(_));
+
+        dahlia.push_str(&token.to_markup(marker));
+        rest = &rest[code_len..];
+    }
+
+    dahlia.push_str(&escape_markers(rest, marker));
+    dahlia
+}
+
+fn push_legacy_code(legacy: &mut String, code: char) {
+    legacy.push(SECTION);
+    legacy.push(code);
+}
+
+/// Parses the `§r§r§g§g§b§b` digits of a `§x` hex color.
+fn bungee_hex(digits: &str) -> Option<[u8; 3]> {
+    let mut chars = digits.chars();
+    let mut nibbles = [0; 6];
+
+    for nibble in &mut nibbles {
+        if chars.next()? != SECTION {
+            return None;
+        }
+        *nibble = chars.next()?.to_digit(16)? as u8;
+    }
+
+    let [r1, r2, g1, g2, b1, b2] = nibbles;
+    Some([(r1, r2), (g1, g2), (b1, b2)].map(|(h, l)| h * 0x10 + l))
+}
+
+/// Returns the style without the background and the formats Minecraft doesn't have.
+fn visible(style: &Style) -> Style {
+    let mut visible = Style::default();
+    // Minecraft has no xterm-256 palette, so those colors are shown as hex
+    visible.fg = style.fg.map(|fg| match fg {
+        Color::Xterm(index) => Color::Rgb(xterm_rgb(index)),
+        color => color,
+    });
+    for (format, _) in FORMATS.iter().filter(|(format, _)| style.has(*format)) {
+        visible.apply(&Token::Format(*format));
+    }
+    // every underline style shows as the single one
+    if style.formats().any(Format::is_underline) {
+        visible.apply(&Token::Format(Format::Underline));
+    }
+    visible
+}
+
+fn component(Run { style, link }: &Run<'_>, text: &str) -> String {
+    let mut json = String::from("{\"text\":");
+    escape_into(&mut json, text);
+
+    match style.fg {
+        Some(Color::Palette(code)) => {
+            let _ = write!(json, ",\"color\":\"{}\"", color_name(code));
+        }
+        Some(Color::Rgb(rgb)) => {
+            let _ = write!(json, ",\"color\":\"{}\"", css_hex(rgb));
+        }
+        Some(Color::Xterm(_)) => unreachable!("`visible` converts xterm colors to hex"),
+        None => {}
+    }
+
+    for (_, key) in FORMATS.iter().filter(|(format, _)| style.has(*format)) {
+        let _ = write!(json, ",\"{key}\":true");
+    }
+
+    if let Some(url) = link {
+        json.push_str(",\"clickEvent\":{\"action\":\"open_url\",\"value\":");
+        escape_into(&mut json, url);
+        json.push('}');
+    }
+
+    json.push('}');
+    json
+}
+
+fn color_name(code: char) -> &'static str {
+    match code {
+        '0' => "black",
+        '1' => "dark_blue",
+        '2' => "dark_green",
+        '3' => "dark_aqua",
+        '4' => "dark_red",
+        '5' => "dark_purple",
+        '6' => "gold",
+        '7' => "gray",
+        '8' => "dark_gray",
+        '9' => "blue",
+        'a' => "green",
+        'b' => "aqua",
+        'c' => "red",
+        'd' => "light_purple",
+        'e' => "yellow",
+        'f' => "white",
+        _ => unreachable!("palette colors should be valid codes"),
+    }
+}
+
+/// Writes `text` as a JSON string literal.
+fn escape_into(json: &mut String, text: &str) {
+    json.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => {
+                let _ = write!(json, "\\u{:04x}", ch as u32);
+            }
+            _ => json.push(ch),
+        }
+    }
+    json.push('"');
+}
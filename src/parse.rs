@@ -0,0 +1,534 @@
+use std::{collections::VecDeque, ops::Range};
+
+use regex::Captures;
+
+use crate::{
+    color::named_color,
+    custom::Definition,
+    gradient::{Gradient, Gradients},
+    scan::{Code, Codes, Scanner},
+    style::Color,
+};
+
+/// Text styles toggled by the formatting codes.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Format {
+    /// `h`
+    Hidden,
+    /// `i`
+    Inverse,
+    /// `j`
+    Dim,
+    /// `k`
+    Blink,
+    /// `l`
+    Bold,
+    /// `m`
+    Strikethrough,
+    /// `n`
+    Underline,
+    /// `o`
+    Italic,
+    /// `s`, a double underline
+    DoubleUnderline,
+    /// `t`, a curly underline
+    CurlyUnderline,
+    /// `u`, a dotted underline
+    DottedUnderline,
+    /// `v`, a dashed underline
+    DashedUnderline,
+    /// `w`
+    Overline,
+}
+
+impl Format {
+    /// All formats, in the order of their codes.
+    pub const ALL: [Format; 13] = [
+        Self::Hidden,
+        Self::Inverse,
+        Self::Dim,
+        Self::Blink,
+        Self::Bold,
+        Self::Strikethrough,
+        Self::Underline,
+        Self::Italic,
+        Self::DoubleUnderline,
+        Self::CurlyUnderline,
+        Self::DottedUnderline,
+        Self::DashedUnderline,
+        Self::Overline,
+    ];
+
+    /// Returns the format for a code (without the marker), e.g. `"l"` for bold.
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.code() == code)
+    }
+
+    /// Returns the Dahlia code of the format (without the marker).
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Hidden => "h",
+            Self::Inverse => "i",
+            Self::Dim => "j",
+            Self::Blink => "k",
+            Self::Bold => "l",
+            Self::Strikethrough => "m",
+            Self::Underline => "n",
+            Self::Italic => "o",
+            Self::DoubleUnderline => "s",
+            Self::CurlyUnderline => "t",
+            Self::DottedUnderline => "u",
+            Self::DashedUnderline => "v",
+            Self::Overline => "w",
+        }
+    }
+
+    /// Returns the name of the format, e.g. `"curly-underline"`, as used in
+    /// the HTML classes and the preview.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Hidden => "hidden",
+            Self::Inverse => "inverse",
+            Self::Dim => "dim",
+            Self::Blink => "blink",
+            Self::Bold => "bold",
+            Self::Strikethrough => "strikethrough",
+            Self::Underline => "underline",
+            Self::Italic => "italic",
+            Self::DoubleUnderline => "double-underline",
+            Self::CurlyUnderline => "curly-underline",
+            Self::DottedUnderline => "dotted-underline",
+            Self::DashedUnderline => "dashed-underline",
+            Self::Overline => "overline",
+        }
+    }
+
+    /// Whether the format is one of the underline styles, only one of which
+    /// can be active at a time.
+    pub fn is_underline(self) -> bool {
+        matches!(
+            self,
+            Self::Underline
+                | Self::DoubleUnderline
+                | Self::CurlyUnderline
+                | Self::DottedUnderline
+                | Self::DashedUnderline
+        )
+    }
+}
+
+/// What a reset code resets.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Reset {
+    /// `R`, resets all formatting
+    All,
+    /// `rf`
+    Foreground,
+    /// `rb`
+    Background,
+    /// `rc`, resets both foreground and background
+    Color,
+    /// `r^`
+    UnderlineColor,
+    /// `rh` to `rw`, resets a single format
+    Format(Format),
+}
+
+impl Reset {
+    /// Returns the reset for a code (without the marker), e.g. `"rb"` for background.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "R" => Some(Self::All),
+            "rf" => Some(Self::Foreground),
+            "rb" => Some(Self::Background),
+            "rc" => Some(Self::Color),
+            "r^" => Some(Self::UnderlineColor),
+            _ => code
+                .strip_prefix('r')
+                .and_then(Format::from_code)
+                .map(Self::Format),
+        }
+    }
+
+    /// Returns the Dahlia code of the reset (without the marker).
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::All => "R",
+            Self::Foreground => "rf",
+            Self::Background => "rb",
+            Self::Color => "rc",
+            Self::UnderlineColor => "r^",
+            Self::Format(Format::Hidden) => "rh",
+            Self::Format(Format::Inverse) => "ri",
+            Self::Format(Format::Dim) => "rj",
+            Self::Format(Format::Blink) => "rk",
+            Self::Format(Format::Bold) => "rl",
+            Self::Format(Format::Strikethrough) => "rm",
+            Self::Format(Format::Underline) => "rn",
+            Self::Format(Format::Italic) => "ro",
+            Self::Format(Format::DoubleUnderline) => "rs",
+            Self::Format(Format::CurlyUnderline) => "rt",
+            Self::Format(Format::DottedUnderline) => "ru",
+            Self::Format(Format::DashedUnderline) => "rv",
+            Self::Format(Format::Overline) => "rw",
+        }
+    }
+}
+
+/// A single element of a Dahlia string.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Token<'a> {
+    /// Plain text between codes
+    Text(&'a str),
+    /// One of the 16 palette colors, `code` is in `0-9a-f`
+    Color { code: char, background: bool },
+    /// A custom `#xxx;` or `#xxxxxx;` color
+    Hex { rgb: [u8; 3], background: bool },
+    /// `@n;`, one of the xterm-256 colors by index
+    Xterm { index: u8, background: bool },
+    /// `^` followed by a palette color, colors the underline
+    UnderlineColor { code: char },
+    /// `^#xxx;` or `^#xxxxxx;`, colors the underline
+    UnderlineHex { rgb: [u8; 3] },
+    /// `^@n;`, colors the underline with an xterm-256 color
+    UnderlineXterm { index: u8 },
+    /// A formatting code
+    Format(Format),
+    /// A reset code
+    Reset(Reset),
+    /// An escaped marker (`&_`), stands for a literal marker
+    Escape,
+    /// `[link:url]`, starts a hyperlink to `url`
+    Link(&'a str),
+    /// `[/link]`, ends the hyperlink
+    LinkEnd,
+}
+
+impl<'a> Token<'a> {
+    /// Builds a token from the captures of the code pattern.
+    pub(crate) fn from_captures(captures: &Captures<'a>) -> Self {
+        if captures.name("esc").is_some() {
+            return Self::Escape;
+        }
+
+        if let Some(url) = captures.name("url") {
+            return Self::Link(url.as_str());
+        }
+
+        if captures.name("linkend").is_some() {
+            return Self::LinkEnd;
+        }
+
+        if let Some(code) = captures.name("fmt") {
+            let code = code.as_str();
+
+            return Format::from_code(code)
+                .map(Self::Format)
+                .or_else(|| Reset::from_code(code).map(Self::Reset))
+                .expect("the regex should match only valid formatter codes or reset codes.");
+        }
+
+        if let Some(code) = captures.name("dahlia1") {
+            return match code.as_str() {
+                "k" => Self::Format(Format::Hidden),
+                "p" => Self::Format(Format::Blink),
+                "r" => Self::Reset(Reset::All),
+                code => Format::from_code(code)
+                    .map(Self::Format)
+                    .expect("the regex should match only valid Dahlia 1.x formatter codes."),
+            };
+        }
+
+        if let Some(name) = captures.name("name") {
+            let name = name.as_str();
+            let background = name.starts_with('~');
+            let underline = name.starts_with('^');
+            let name = name.trim_start_matches(['~', '^']);
+
+            return match named_color(&name[1..name.len() - 1]) {
+                Some(color) => Self::colored(color, background, underline),
+                None => unreachable!("the regex should match only known color names"),
+            };
+        }
+
+        let background = captures.name("bg").is_some();
+        let underline = captures.name("ul").is_some();
+
+        if let Some(hex) = captures.name("hex") {
+            return Self::colored(Color::Rgb(parse_hex(hex.as_str())), background, underline);
+        }
+
+        if let Some(index) = captures.name("xterm") {
+            let index = index
+                .as_str()
+                .parse()
+                .expect("the regex should match only indices from 0 to 255");
+            return Self::colored(Color::Xterm(index), background, underline);
+        }
+
+        // if it's not a formatter, hex or xterm code, it's a color code
+        let code = captures["color"]
+            .chars()
+            .next()
+            .expect("the regex should match exactly one color character");
+
+        if code == 'g' {
+            // minecoin gold, only a code in Dahlia 1.x
+            return Self::Hex {
+                rgb: [0xdd, 0xd6, 0x05],
+                background,
+            };
+        }
+
+        Self::colored(Color::Palette(code), background, underline)
+    }
+
+    /// Builds the token setting the foreground, background or underline color.
+    pub(crate) fn colored(color: Color, background: bool, underline: bool) -> Self {
+        match (color, underline) {
+            (Color::Palette(code), false) => Self::Color { code, background },
+            (Color::Rgb(rgb), false) => Self::Hex { rgb, background },
+            (Color::Xterm(index), false) => Self::Xterm { index, background },
+            (Color::Palette(code), true) => Self::UnderlineColor { code },
+            (Color::Rgb(rgb), true) => Self::UnderlineHex { rgb },
+            (Color::Xterm(index), true) => Self::UnderlineXterm { index },
+        }
+    }
+
+    /// Detaches a code from the parsed string.
+    ///
+    /// Panics for text and links, which borrow from the string.
+    pub(crate) fn into_static(self) -> Token<'static> {
+        match self {
+            Self::Text(_) | Self::Link(_) => {
+                panic!("text and links can't outlive the parsed string")
+            }
+            Self::Color { code, background } => Token::Color { code, background },
+            Self::Hex { rgb, background } => Token::Hex { rgb, background },
+            Self::Xterm { index, background } => Token::Xterm { index, background },
+            Self::UnderlineColor { code } => Token::UnderlineColor { code },
+            Self::UnderlineXterm { index } => Token::UnderlineXterm { index },
+            Self::UnderlineHex { rgb } => Token::UnderlineHex { rgb },
+            Self::Format(format) => Token::Format(format),
+            Self::Reset(reset) => Token::Reset(reset),
+            Self::Escape => Token::Escape,
+            Self::LinkEnd => Token::LinkEnd,
+        }
+    }
+
+    /// Writes the token back as Dahlia markup using the given marker.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::{Format, Token};
+    /// assert_eq!(Token::Format(Format::Bold).to_markup('&'), "&l");
+    /// assert_eq!(Token::Hex { rgb: [255, 0, 255], background: true }.to_markup('§'), "§~#ff00ff;");
+    /// ```
+    pub fn to_markup(&self, marker: char) -> String {
+        let bg = |background: bool| if background { "~" } else { "" };
+
+        match *self {
+            Self::Text(text) => text.to_owned(),
+            Self::Color { code, background } => format!("{marker}{}{code}", bg(background)),
+            Self::Hex {
+                rgb: [r, g, b],
+                background,
+            } => format!("{marker}{}#{r:02x}{g:02x}{b:02x};", bg(background)),
+            Self::Xterm { index, background } => format!("{marker}{}@{index};", bg(background)),
+            Self::UnderlineColor { code } => format!("{marker}^{code}"),
+            Self::UnderlineXterm { index } => format!("{marker}^@{index};"),
+            Self::UnderlineHex { rgb: [r, g, b] } => format!("{marker}^#{r:02x}{g:02x}{b:02x};"),
+            Self::Format(format) => format!("{marker}{}", format.code()),
+            Self::Reset(reset) => format!("{marker}{}", reset.code()),
+            Self::Escape => format!("{marker}_"),
+            Self::Link(url) => format!("{marker}[link:{url}]"),
+            Self::LinkEnd => format!("{marker}[/link]"),
+        }
+    }
+}
+
+/// A token together with its byte range in the parsed string.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Spanned<'a> {
+    pub token: Token<'a>,
+    pub span: Range<usize>,
+}
+
+/// Iterator over the tokens of a Dahlia string, created by [`Dahlia::parse`](crate::Dahlia::parse).
+///
+/// Gradients are resolved while parsing: the text they apply to is split into
+/// graphemes, each preceded by its [`Token::Hex`] color (with an empty span).
+pub struct Tokens<'r, 'a> {
+    input: &'a str,
+    scanner: Scanner<'r>,
+    codes: Codes<'r, 'a>,
+    // whether the SGR parameters of defined codes are yielded too
+    sgr: bool,
+    // whether defined codes are yielded as they are instead of their tokens
+    defined: bool,
+    // end of the last parsed code or text
+    position: usize,
+    gradients: Gradients,
+    // tokens found along with the last code, yielded on the next calls
+    pending: VecDeque<Item<'r, 'a>>,
+}
+
+/// A token, or the SGR parameters of a defined code, which only `convert` writes.
+pub(crate) enum Item<'r, 'a> {
+    Token(Spanned<'a>),
+    Sgr(&'r str),
+    /// A defined code by name, see [`Tokens::with_defined`]
+    Defined(&'a str, &'r Definition),
+}
+
+impl<'r, 'a> Tokens<'r, 'a> {
+    pub(crate) fn new(input: &'a str, scanner: Scanner<'r>) -> Self {
+        Self {
+            input,
+            scanner,
+            codes: scanner.codes(input),
+            sgr: false,
+            defined: false,
+            position: 0,
+            gradients: Gradients::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Also yields the SGR parameters of defined codes, see [`Tokens::next_item`].
+    pub(crate) fn with_sgr(mut self) -> Self {
+        self.sgr = true;
+        self
+    }
+
+    /// Yields defined codes as [`Item::Defined`] instead of the tokens they
+    /// expand to, for writing them back as markup.
+    pub(crate) fn with_defined(mut self) -> Self {
+        self.defined = true;
+        self
+    }
+
+    /// Returns the next token, or the SGR parameters of a defined code.
+    pub(crate) fn next_item(&mut self) -> Option<Item<'r, 'a>> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+
+            let Some((code, span)) = self.codes.next() else {
+                if self.position == self.input.len() {
+                    return None;
+                }
+                self.push_text(self.input.len());
+                continue;
+            };
+
+            self.push_text(span.start);
+            self.position = span.end;
+
+            match code {
+                Code::Gradient { stops, background } => {
+                    let rest = &self.input[span.end..];
+                    let gradient = Gradient::new(stops, background, rest, self.scanner);
+                    self.gradients.start(gradient);
+                }
+                // every code of a definition has the span of the defined code
+                Code::Defined(name) => {
+                    let definition = &self.scanner.definitions[name];
+
+                    if self.defined {
+                        definition
+                            .tokens
+                            .iter()
+                            .for_each(|token| self.gradients.end_at(token));
+                        self.pending.push_back(Item::Defined(name, definition));
+                        continue;
+                    }
+
+                    for &token in &definition.tokens {
+                        self.push_code(token, span.clone());
+                    }
+                    if self.sgr {
+                        self.pending
+                            .extend(definition.sgr.iter().map(|params| Item::Sgr(params)));
+                    }
+                }
+                Code::Token(token) => self.push_code(token, span),
+            }
+        }
+    }
+
+    fn push_code(&mut self, token: Token<'a>, span: Range<usize>) {
+        if token == Token::Escape {
+            // an escaped marker is colored like any other character
+            let marker = &self.input[span.start..span.end - 1];
+            let colors: Vec<_> = self.gradients.next(marker).collect();
+            for color in colors {
+                self.push(color, span.start..span.start);
+            }
+        }
+
+        self.gradients.end_at(&token);
+        self.push(token, span);
+    }
+
+    /// Queues the text from the current position up to `end`.
+    fn push_text(&mut self, end: usize) {
+        let start = self.position;
+        self.position = end;
+        if start == end {
+            return;
+        }
+
+        if !self.gradients.is_active() {
+            self.push(Token::Text(&self.input[start..end]), start..end);
+            return;
+        }
+
+        let input = self.input;
+        let mut painted = Vec::new();
+        self.gradients.paint(&input[start..end], |color, range| {
+            let span = start + range.start..start + range.end;
+            painted.push(match color {
+                Some(color) => Spanned { token: color, span },
+                None => Spanned {
+                    token: Token::Text(&input[span.clone()]),
+                    span,
+                },
+            });
+        });
+        self.pending.extend(painted.into_iter().map(Item::Token));
+    }
+
+    fn push(&mut self, token: Token<'a>, span: Range<usize>) {
+        self.pending.push_back(Item::Token(Spanned { token, span }));
+    }
+}
+
+impl<'a> Iterator for Tokens<'_, 'a> {
+    type Item = Spanned<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_item()? {
+                Item::Token(spanned) => return Some(spanned),
+                Item::Sgr(_) | Item::Defined(..) => {}
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_hex(hex: &str) -> [u8; 3] {
+    let hex_digits = hex
+        .chars()
+        .map(|ch| ch.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()
+        .expect("the regex should only match valid hexadecimal digits");
+
+    match hex_digits[..] {
+        // if there are only 3 digits, "duplicate" each
+        [r, g, b] => [r, g, b].map(|d| 0x11 * d),
+        [r1, r2, g1, g2, b1, b2] => [(r1, r2), (g1, g2), (b1, b2)].map(|(h, l)| h * 0x10 + l),
+        _ => unreachable!("the regex should only match codes of length 3 or 6"),
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{Dahlia, Depth, RESET};
+
+/// A string converted at compile time for every color depth.
+///
+/// Created by the `dahlia!` macro from the
+/// [`dahlia-macros`](https://docs.rs/dahlia-macros) crate, which also reports
+/// unknown format codes as compile errors. Hex colors are downsampled with the
+/// default [`ColorDistance`](crate::ColorDistance), and palette colors always
+/// use the default [`Theme`](crate::Theme).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Precompiled {
+    plain: &'static str,
+    // converted with the automatic reset, indexed by `depth_index`
+    converted: [&'static str; 4],
+    // whether the reset at the end was added automatically
+    appended_reset: bool,
+}
+
+impl Precompiled {
+    #[doc(hidden)]
+    pub const fn __new(
+        plain: &'static str,
+        converted: [&'static str; 4],
+        appended_reset: bool,
+    ) -> Self {
+        Self {
+            plain,
+            converted,
+            appended_reset,
+        }
+    }
+
+    /// Returns the string for the depth and auto reset setting of a Dahlia instance.
+    pub fn render(&self, dahlia: &Dahlia) -> &'static str {
+        let converted = self.for_depth(dahlia.depth);
+
+        if dahlia.depth.is_some() && self.appended_reset && !dahlia.auto_reset {
+            &converted[..converted.len() - RESET.len()]
+        } else {
+            converted
+        }
+    }
+
+    /// Returns the string for a depth (with the automatic reset), or without
+    /// any formatting for `None`.
+    pub fn for_depth(&self, depth: Option<Depth>) -> &'static str {
+        match depth {
+            Some(depth) => self.converted[depth_index(depth)],
+            None => self.plain,
+        }
+    }
+}
+
+fn depth_index(depth: Depth) -> usize {
+    match depth {
+        Depth::Tty => 0,
+        Depth::Low => 1,
+        Depth::Medium => 2,
+        Depth::High => 3,
+    }
+}
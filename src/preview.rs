@@ -0,0 +1,159 @@
+use std::{
+    env,
+    fmt::Write,
+    io::{self, IsTerminal},
+};
+
+use crate::{color::COLOR_CODES, Dahlia, Depth, Format, Reset};
+
+const DEPTHS: [Depth; 4] = [Depth::Tty, Depth::Low, Depth::Medium, Depth::High];
+// width of the labels in the first column
+const LABEL_WIDTH: usize = 10;
+const GRADIENT_WIDTH: usize = 16;
+// samples per line in the format and reset rows
+const ROW_LENGTH: usize = 7;
+
+/// Renders the report returned by [`Dahlia::preview`].
+pub(crate) fn render(dahlia: &Dahlia) -> String {
+    let mut preview = String::new();
+
+    let _ = writeln!(
+        preview,
+        "Detected depth: {}",
+        depth_name(Depth::try_infer())
+    );
+    let _ = writeln!(
+        preview,
+        "  TERM={}  COLORTERM={}  NO_COLOR={}  stdout is {}a terminal",
+        env_var("TERM"),
+        env_var("COLORTERM"),
+        env_var("NO_COLOR"),
+        if io::stdout().is_terminal() {
+            ""
+        } else {
+            "not "
+        },
+    );
+    let _ = writeln!(preview);
+
+    let _ = writeln!(
+        preview,
+        "{:LABEL_WIDTH$}{:<18}{:<18}Gradients",
+        "", "Foreground", "Background"
+    );
+    for depth in DEPTHS {
+        let row = palette_row(&sample_dahlia(dahlia, Some(depth)));
+        let _ = writeln!(preview, "{:LABEL_WIDTH$}{row}", depth_name(Some(depth)));
+    }
+    let _ = writeln!(preview);
+
+    // formats barely depend on the depth, so they are only shown at the
+    // depth of `dahlia`
+    let _ = writeln!(
+        preview,
+        "Formats and resets at {}:",
+        depth_name(dahlia.depth)
+    );
+    let sample = sample_dahlia(dahlia, dahlia.depth);
+    let rows = labeled_rows("formats", &format_samples())
+        .into_iter()
+        .chain(labeled_rows("resets", &reset_samples()));
+    for row in rows {
+        let _ = writeln!(preview, "{}", sample.convert(&row));
+    }
+
+    preview
+}
+
+/// A default `Dahlia` for the sample markup, with the colors of `dahlia`.
+fn sample_dahlia(dahlia: &Dahlia, depth: Option<Depth>) -> Dahlia {
+    let mut sample = Dahlia::new(None, true, '&')
+        .with_theme(dahlia.theme.clone())
+        .with_color_distance(dahlia.distance);
+    // set directly, as the preview should show colors even with NO_COLOR
+    sample.depth = depth;
+    sample
+}
+
+fn palette_row(dahlia: &Dahlia) -> String {
+    let mut markup = String::new();
+
+    for code in COLOR_CODES {
+        let _ = write!(markup, "&{code}{code}");
+    }
+    markup.push_str("&rf  ");
+    for code in COLOR_CODES {
+        let _ = write!(markup, "&~{code}{code}");
+    }
+    markup.push_str("&rb  ");
+
+    let bar = " ".repeat(GRADIENT_WIDTH);
+    let _ = write!(
+        markup,
+        "&~[gradient:#ff5555:#ffff55:#5555ff]{bar}&rb &~[rainbow]{bar}&rb"
+    );
+
+    dahlia.convert(&markup).into_owned()
+}
+
+/// Splits the samples into lines, the first one labeled with `label`.
+fn labeled_rows(label: &str, samples: &[String]) -> Vec<String> {
+    samples
+        .chunks(ROW_LENGTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let label = if i == 0 { label } else { "" };
+            format!("{label:LABEL_WIDTH$}{}", chunk.join("  "))
+        })
+        .collect()
+}
+
+fn format_samples() -> Vec<String> {
+    Format::ALL
+        .into_iter()
+        .map(|format| {
+            let code = format.code();
+            format!("&_{code} &{code}{}&R", format.name())
+        })
+        .collect()
+}
+
+fn reset_samples() -> Vec<String> {
+    let resets = [
+        Reset::All,
+        Reset::Foreground,
+        Reset::Background,
+        Reset::Color,
+        Reset::UnderlineColor,
+    ]
+    .into_iter()
+    .chain(Format::ALL.map(Reset::Format));
+
+    resets
+        .map(|reset| {
+            // what the reset turns off, followed by some text without it
+            let before = match reset {
+                Reset::All => "&c&~8&l".to_owned(),
+                Reset::Foreground | Reset::Background | Reset::Color => "&c&~8".to_owned(),
+                Reset::UnderlineColor => "&n&^c".to_owned(),
+                Reset::Format(format) => format!("&{}", format.code()),
+            };
+            let code = reset.code();
+            format!("&_{code} {before}on&{code}off&R")
+        })
+        .collect()
+}
+
+fn depth_name(depth: Option<Depth>) -> &'static str {
+    match depth {
+        Some(Depth::Tty) => "tty",
+        Some(Depth::Low) => "low",
+        Some(Depth::Medium) => "medium",
+        Some(Depth::High) => "high",
+        None => "none (no colors)",
+    }
+}
+
+fn env_var(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| "(unset)".to_owned())
+}
@@ -0,0 +1,328 @@
+use std::{collections::HashMap, ops::Range};
+
+#[cfg(test)]
+use regex::CaptureMatches;
+use regex::Captures;
+#[cfg(test)]
+use regex::Regex;
+
+use crate::{
+    custom::{self, Definition},
+    parse::parse_hex,
+    Format, Patterns, Reset, SpecVersion, Token,
+};
+
+/// What a byte following the marker starts.
+#[derive(Copy, Clone)]
+enum Entry {
+    None,
+    // with its index in the palette
+    Color(u8),
+    Format(Format),
+    // `R`
+    ResetAll,
+    // `r`, followed by the code of what is reset
+    Reset,
+    // `~` and `^`, followed by a color
+    Background,
+    Underline,
+    Hex,
+    Xterm,
+    Escape,
+    // `[`, named colors, gradients, links and defined codes
+    Bracket,
+}
+
+const TABLE: [Entry; 128] = table();
+
+const fn table() -> [Entry; 128] {
+    let mut table = [Entry::None; 128];
+
+    let mut index = 0;
+    while index < 16 {
+        let byte = if index < 10 {
+            b'0' + index
+        } else {
+            b'a' + index - 10
+        };
+        table[byte as usize] = Entry::Color(index);
+        index += 1;
+    }
+
+    table[b'h' as usize] = Entry::Format(Format::Hidden);
+    table[b'i' as usize] = Entry::Format(Format::Inverse);
+    table[b'j' as usize] = Entry::Format(Format::Dim);
+    table[b'k' as usize] = Entry::Format(Format::Blink);
+    table[b'l' as usize] = Entry::Format(Format::Bold);
+    table[b'm' as usize] = Entry::Format(Format::Strikethrough);
+    table[b'n' as usize] = Entry::Format(Format::Underline);
+    table[b'o' as usize] = Entry::Format(Format::Italic);
+    table[b's' as usize] = Entry::Format(Format::DoubleUnderline);
+    table[b't' as usize] = Entry::Format(Format::CurlyUnderline);
+    table[b'u' as usize] = Entry::Format(Format::DottedUnderline);
+    table[b'v' as usize] = Entry::Format(Format::DashedUnderline);
+    table[b'w' as usize] = Entry::Format(Format::Overline);
+    table[b'R' as usize] = Entry::ResetAll;
+    table[b'r' as usize] = Entry::Reset;
+    table[b'~' as usize] = Entry::Background;
+    table[b'^' as usize] = Entry::Underline;
+    table[b'#' as usize] = Entry::Hex;
+    table[b'@' as usize] = Entry::Xterm;
+    table[b'_' as usize] = Entry::Escape;
+    table[b'[' as usize] = Entry::Bracket;
+
+    table
+}
+
+/// A code found after a marker.
+enum Scanned {
+    // the token and the length of the code, without the marker
+    Code(Token<'static>, usize),
+    Escape,
+    Bracket,
+    None,
+}
+
+/// A code found by [`Codes`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Code<'a> {
+    /// A built-in code, or an escaped marker
+    Token(Token<'a>),
+    /// `[gradient:...]` with its `:#xxx:#xxx` stops, or `[rainbow]` without
+    Gradient {
+        stops: Option<&'a str>,
+        background: bool,
+    },
+    /// A code registered with `define` or `define_sgr`, by name
+    Defined(&'a str),
+}
+
+impl<'a> Code<'a> {
+    fn from_captures(captures: &Captures<'a>) -> Self {
+        if captures.name("gradient").is_some() {
+            Self::Gradient {
+                stops: captures.name("stops").map(|stops| stops.as_str()),
+                background: captures.name("gbg").is_some(),
+            }
+        } else if let Some(name) = captures.name("custom") {
+            Self::Defined(custom::name(name.as_str()))
+        } else {
+            Self::Token(Token::from_captures(captures))
+        }
+    }
+}
+
+/// Finds the codes of a string for a Dahlia instance.
+///
+/// The built-in Dahlia 2 codes (and single character definitions) are found
+/// by hand, in a single pass. Codes in brackets and Dahlia 1.x codes are
+/// matched with the code pattern, anchored at their marker.
+#[derive(Clone, Copy)]
+pub(crate) struct Scanner<'r> {
+    pub marker: char,
+    pub spec: SpecVersion,
+    pub definitions: &'r HashMap<String, Definition>,
+    // finds the marker, and the codes left to the regex
+    pub patterns: &'r Patterns,
+    // the code pattern, if every code is found with it instead
+    #[cfg(test)]
+    pub regex: Option<&'r Regex>,
+}
+
+impl<'r> Scanner<'r> {
+    pub fn codes<'a>(self, input: &'a str) -> Codes<'r, 'a> {
+        Codes {
+            input,
+            scanner: self,
+            #[cfg(test)]
+            matches: self.regex.map(|regex| regex.captures_iter(input)),
+            search: 0,
+        }
+    }
+
+    /// Returns the code at the start of `rest`, which follows a marker, and
+    /// its length.
+    fn scan<'a>(&self, rest: &'a str) -> Option<(Code<'a>, usize)> {
+        if self.spec == SpecVersion::Dahlia2 {
+            match scan(rest.as_bytes()) {
+                Scanned::Code(token, len) => return Some((Code::Token(token), len)),
+                Scanned::Escape => return Some((Code::Token(Token::Escape), 1)),
+                Scanned::None => {
+                    // single characters can only be defined if they start no built-in code
+                    let name = rest.get(..1)?;
+                    return self
+                        .definitions
+                        .contains_key(name)
+                        .then_some((Code::Defined(name), 1));
+                }
+                Scanned::Bracket => {}
+            }
+        }
+
+        let captures = self.patterns.anchored(self.definitions).captures(rest)?;
+        let len = captures.get(0).expect("group 0 is always present").len();
+        Some((Code::from_captures(&captures), len))
+    }
+}
+
+/// Iterator over the codes of a string and their byte ranges (including the
+/// marker), created by [`Scanner::codes`].
+pub(crate) struct Codes<'r, 'a> {
+    input: &'a str,
+    scanner: Scanner<'r>,
+    #[cfg(test)]
+    matches: Option<CaptureMatches<'r, 'a>>,
+    // where the next marker is looked for
+    search: usize,
+}
+
+impl<'a> Iterator for Codes<'_, 'a> {
+    type Item = (Code<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        #[cfg(test)]
+        if let Some(matches) = &mut self.matches {
+            let captures = matches.next()?;
+            let code = captures.get(0).expect("group 0 is always present");
+            return Some((Code::from_captures(&captures), code.range()));
+        }
+
+        let bytes = self.input.as_bytes();
+        while let Some(found) = self.scanner.patterns.finder().find(&bytes[self.search..]) {
+            let start = self.search + found;
+            self.search = start + self.scanner.marker.len_utf8();
+
+            if let Some((code, len)) = self.scanner.scan(&self.input[self.search..]) {
+                self.search += len;
+                return Some((code, start..self.search));
+            }
+        }
+
+        self.search = bytes.len();
+        None
+    }
+}
+
+fn scan(rest: &[u8]) -> Scanned {
+    let Some(&first) = rest.first() else {
+        return Scanned::None;
+    };
+
+    match entry(first) {
+        Entry::Color(_) => Scanned::Code(palette(first, false, false), 1),
+        Entry::Format(format) => Scanned::Code(Token::Format(format), 1),
+        Entry::ResetAll => Scanned::Code(Token::Reset(Reset::All), 1),
+        Entry::Reset => match rest.get(1).copied().and_then(reset) {
+            Some(reset) => Scanned::Code(Token::Reset(reset), 2),
+            None => Scanned::None,
+        },
+        Entry::Background | Entry::Underline => {
+            let background = first == b'~';
+            match color(&rest[1..], background, !background) {
+                Some((token, len)) => Scanned::Code(token, len + 1),
+                None if rest.get(1) == Some(&b'[') => Scanned::Bracket,
+                None => Scanned::None,
+            }
+        }
+        Entry::Hex | Entry::Xterm => match color(rest, false, false) {
+            Some((token, len)) => Scanned::Code(token, len),
+            None => Scanned::None,
+        },
+        Entry::Escape => Scanned::Escape,
+        Entry::Bracket => Scanned::Bracket,
+        Entry::None => Scanned::None,
+    }
+}
+
+/// Whether a built-in code starts with `byte`, right after the marker.
+pub(crate) fn starts_code(byte: u8) -> bool {
+    !matches!(entry(byte), Entry::None)
+}
+
+/// Returns the palette index of a color code.
+pub(crate) fn color_index(code: char) -> usize {
+    match u8::try_from(code).map(entry) {
+        Ok(Entry::Color(index)) => usize::from(index),
+        _ => panic!("`{code}` is not a color code (0-9, a-f)"),
+    }
+}
+
+fn entry(byte: u8) -> Entry {
+    TABLE.get(usize::from(byte)).copied().unwrap_or(Entry::None)
+}
+
+/// Scans a palette, hex or xterm color, returning its token and length.
+fn color(rest: &[u8], background: bool, underline: bool) -> Option<(Token<'static>, usize)> {
+    let first = *rest.first()?;
+
+    match entry(first) {
+        Entry::Color(_) => Some((palette(first, background, underline), 1)),
+        Entry::Hex => {
+            let digits = rest[1..]
+                .iter()
+                .take(6)
+                .take_while(|&&byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+                .count();
+            // `#xxx;` is tried first, like in the regex
+            let len = [3, 6]
+                .into_iter()
+                .find(|&len| digits >= len && rest.get(len + 1) == Some(&b';'))?;
+            let hex = std::str::from_utf8(&rest[1..=len]).expect("hex digits are ASCII");
+            let rgb = parse_hex(hex);
+
+            let token = if underline {
+                Token::UnderlineHex { rgb }
+            } else {
+                Token::Hex { rgb, background }
+            };
+            Some((token, len + 2))
+        }
+        Entry::Xterm => {
+            let digits = rest[1..]
+                .iter()
+                .take(3)
+                .take_while(|byte| byte.is_ascii_digit())
+                .count();
+            // no leading zeros, and 255 at most
+            if digits == 0 || (digits > 1 && rest[1] == b'0') || rest.get(digits + 1) != Some(&b';')
+            {
+                return None;
+            }
+            let index = rest[1..=digits]
+                .iter()
+                .fold(0u16, |index, digit| index * 10 + u16::from(digit - b'0'));
+            let index = u8::try_from(index).ok()?;
+
+            let token = if underline {
+                Token::UnderlineXterm { index }
+            } else {
+                Token::Xterm { index, background }
+            };
+            Some((token, digits + 2))
+        }
+        _ => None,
+    }
+}
+
+fn palette(byte: u8, background: bool, underline: bool) -> Token<'static> {
+    let code = char::from(byte);
+
+    if underline {
+        Token::UnderlineColor { code }
+    } else {
+        Token::Color { code, background }
+    }
+}
+
+fn reset(byte: u8) -> Option<Reset> {
+    match byte {
+        b'b' => Some(Reset::Background),
+        b'c' => Some(Reset::Color),
+        b'f' => Some(Reset::Foreground),
+        b'^' => Some(Reset::UnderlineColor),
+        _ => match entry(byte) {
+            Entry::Format(format) => Some(Reset::Format(format)),
+            _ => None,
+        },
+    }
+}
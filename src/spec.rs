@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use crate::{
+    color::xterm_rgb, escape_markers, scan::Scanner, style::css_hex, Format, Patterns, Reset,
+    Spanned, Token, Tokens,
+};
+
+/// The set of format codes understood by a [`Dahlia`](crate::Dahlia) instance.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum SpecVersion {
+    /// The codes of Dahlia 1.x: `&[#rrggbb]` hex colors, `&r` full reset,
+    /// `&p` blink, `&k` hidden and the `&g` (minecoin gold) color
+    Dahlia1,
+    /// The codes of the Dahlia specification v1.0.0, used since Dahlia 2.0
+    #[default]
+    Dahlia2,
+}
+
+/// Rewrites a string written with Dahlia 1.x codes into Dahlia 2.0 codes.
+///
+/// Every code is replaced with its 2.0 equivalent, `&g` becomes the hex
+/// color `&#ddd605;`. Markers that aren't part of a code were shown as they
+/// are by Dahlia 1.x, so they're escaped.
+///
+/// # Example
+///
+/// ```rust
+/// # use dahlia::migrate_from_dahlia1;
+/// let old = "&[#ffaff3]&kSecret&r &pblink &gR&D";
+/// assert_eq!(
+///     migrate_from_dahlia1(old, '&'),
+///     "&#ffaff3;&hSecret&R &kblink &#ddd605;R&_D"
+/// );
+/// ```
+pub fn migrate_from_dahlia1(string: &str, marker: char) -> String {
+    // the Dahlia 1.x code pattern is compiled once and shared, only the
+    // finder of the marker is built per call
+    let patterns = Patterns::new(marker, SpecVersion::Dahlia1, false);
+    let scanner = Scanner {
+        marker,
+        spec: SpecVersion::Dahlia1,
+        definitions: &HashMap::new(),
+        patterns: &patterns,
+        #[cfg(test)]
+        regex: None,
+    };
+
+    Tokens::new(string, scanner)
+        .map(|Spanned { token, .. }| match token {
+            Token::Text(text) => escape_markers(text, marker),
+            code => code.to_markup(marker),
+        })
+        .collect()
+}
+
+/// Writes a code as the Dahlia 1.x code closest to it, or returns `None` if
+/// Dahlia 1.x has nothing like it (underline colors, overline and links).
+///
+/// xterm-256 colors become hex colors and every underline style becomes
+/// `&n`. Resets other than the full reset have no equivalent either, as they
+/// depend on the styling they apply to.
+pub(crate) fn dahlia1_markup(token: &Token<'_>, marker: char) -> Option<String> {
+    let bg = |background: bool| if background { "~" } else { "" };
+
+    let markup = match *token {
+        Token::Color { code, background } => format!("{marker}{}{code}", bg(background)),
+        Token::Hex { rgb, background } => format!("{marker}{}[{}]", bg(background), css_hex(rgb)),
+        Token::Xterm { index, background } => {
+            format!("{marker}{}[{}]", bg(background), css_hex(xterm_rgb(index)))
+        }
+        Token::Format(Format::Hidden) => format!("{marker}k"),
+        Token::Format(Format::Blink) => format!("{marker}p"),
+        Token::Format(format) if format.is_underline() => format!("{marker}n"),
+        Token::Format(Format::Overline) => return None,
+        Token::Format(format) => format!("{marker}{}", format.code()),
+        Token::Reset(Reset::All) => format!("{marker}r"),
+        Token::Escape => format!("{marker}_"),
+        Token::Text(text) => text.to_owned(),
+        Token::UnderlineColor { .. }
+        | Token::UnderlineHex { .. }
+        | Token::UnderlineXterm { .. }
+        | Token::Reset(_)
+        | Token::Link(_)
+        | Token::LinkEnd => return None,
+    };
+
+    Some(markup)
+}
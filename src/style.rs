@@ -0,0 +1,141 @@
+use crate::{color::xterm_rgb, scan, Format, Reset, Theme, Token};
+
+/// A color set by a color or hex code.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub(crate) enum Color {
+    Palette(char),
+    Rgb([u8; 3]),
+    Xterm(u8),
+}
+
+impl Color {
+    pub fn rgb(self, theme: &Theme) -> [u8; 3] {
+        match self {
+            Self::Palette(code) => theme.rgb[scan::color_index(code)],
+            Self::Rgb(rgb) => rgb,
+            Self::Xterm(index) => xterm_rgb(index),
+        }
+    }
+
+    pub fn css(self, theme: &Theme) -> String {
+        css_hex(self.rgb(theme))
+    }
+}
+
+/// Formats a color as `#rrggbb`.
+pub(crate) fn css_hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// The styling in effect at some point of a Dahlia string.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub(crate) struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    // underline color
+    pub ul: Option<Color>,
+    formats: u16,
+}
+
+impl Style {
+    /// Updates the style with a token, text, escapes and links leave it unchanged.
+    pub fn apply(&mut self, token: &Token<'_>) {
+        match *token {
+            Token::Color { code, background } => self.set_color(Color::Palette(code), background),
+            Token::Hex { rgb, background } => self.set_color(Color::Rgb(rgb), background),
+            Token::Xterm { index, background } => self.set_color(Color::Xterm(index), background),
+            Token::UnderlineColor { code } => self.ul = Some(Color::Palette(code)),
+            Token::UnderlineHex { rgb } => self.ul = Some(Color::Rgb(rgb)),
+            Token::UnderlineXterm { index } => self.ul = Some(Color::Xterm(index)),
+            Token::Format(format) => {
+                // a new underline style replaces the previous one
+                if format.is_underline() {
+                    self.formats &= !underline_bits();
+                }
+                self.formats |= bit(format);
+            }
+            Token::Reset(Reset::All) => *self = Self::default(),
+            Token::Reset(Reset::Foreground) => self.fg = None,
+            Token::Reset(Reset::Background) => self.bg = None,
+            Token::Reset(Reset::Color) => (self.fg, self.bg) = (None, None),
+            Token::Reset(Reset::UnderlineColor) => self.ul = None,
+            // terminals have a single reset for every underline style
+            Token::Reset(Reset::Format(format)) if format.is_underline() => {
+                self.formats &= !underline_bits();
+            }
+            Token::Reset(Reset::Format(format)) => self.formats &= !bit(format),
+            Token::Text(_) | Token::Escape | Token::Link(_) | Token::LinkEnd => {}
+        }
+    }
+
+    pub fn has(&self, format: Format) -> bool {
+        self.formats & bit(format) != 0
+    }
+
+    pub fn formats(&self) -> impl Iterator<Item = Format> + '_ {
+        Format::ALL.into_iter().filter(|&format| self.has(format))
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the codes that set up this style from a plain one.
+    pub fn tokens(&self) -> impl Iterator<Item = Token<'static>> + '_ {
+        let colors = [
+            (self.fg, false, false),
+            (self.bg, true, false),
+            (self.ul, false, true),
+        ]
+        .into_iter()
+        .filter_map(|(color, background, underline)| {
+            Some(Token::colored(color?, background, underline))
+        });
+
+        colors.chain(self.formats().map(Token::Format))
+    }
+
+    /// Returns the resets that undo what this style sets and `target` doesn't.
+    pub fn resets_to<'s>(&'s self, target: &'s Style) -> impl Iterator<Item = Token<'static>> + 's {
+        let colors = [
+            (self.fg, target.fg, Reset::Foreground),
+            (self.bg, target.bg, Reset::Background),
+            (self.ul, target.ul, Reset::UnderlineColor),
+        ]
+        .into_iter()
+        .filter(|(color, target, _)| color.is_some() && target.is_none())
+        .map(|(_, _, reset)| Token::Reset(reset));
+
+        let formats = self
+            .formats()
+            .filter(|&format| !target.has(format))
+            .map(|format| Token::Reset(Reset::Format(format)));
+
+        colors.chain(formats)
+    }
+
+    fn set_color(&mut self, color: Color, background: bool) {
+        if background {
+            self.bg = Some(color);
+        } else {
+            self.fg = Some(color);
+        }
+    }
+}
+
+fn bit(format: Format) -> u16 {
+    let index = Format::ALL
+        .iter()
+        .position(|&f| f == format)
+        .expect("every format should be listed in Format::ALL");
+
+    1 << index
+}
+
+fn underline_bits() -> u16 {
+    Format::ALL
+        .into_iter()
+        .filter(|format| format.is_underline())
+        .map(bit)
+        .fold(0, |bits, bit| bits | bit)
+}
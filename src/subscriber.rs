@@ -0,0 +1,323 @@
+use std::{
+    fmt::{self, Write},
+    io,
+};
+
+use tracing_core::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{FormatEvent, FormatFields, Writer},
+        FmtContext, FormattedFields, Layer,
+    },
+    registry::LookupSpan,
+};
+
+use crate::{escape_markers, Dahlia, Depth};
+
+/// A [`FormatEvent`] implementation that writes `tracing` events with Dahlia
+/// codes, available with the `tracing` feature.
+///
+/// Every event is written as its level prefix, the names and fields of the
+/// spans it is in, its target, its message and its fields, and the whole line
+/// is converted, so messages can contain codes too. Span names, targets,
+/// field names and field values are escaped, only the templates and styles
+/// are markup.
+///
+/// The color depth is inferred for stdout (see [`Depth::try_infer_for`]),
+/// and colors are left out when the layer has ANSI escapes turned off, which
+/// `tracing-subscriber` does when `NO_COLOR` is set.
+///
+/// ### Example
+/// ```rust
+/// # use dahlia::DahliaFormat;
+/// use tracing_subscriber::prelude::*;
+///
+/// tracing_subscriber::registry()
+///     .with(DahliaFormat::new().with_target_style("&9").layer())
+///     .init();
+///
+/// tracing::info!(port = 8080, "server &lstarted");
+/// ```
+pub struct DahliaFormat {
+    dahlia: Dahlia,
+    // level prefixes, indexed by `level_index`
+    templates: [String; 5],
+    target_style: String,
+    span_style: String,
+    field_style: String,
+}
+
+/// The [`FormatFields`] implementation used with [`DahliaFormat`], see
+/// [`DahliaFormat::fields`].
+///
+/// Field names are written with the field style, and names and values are
+/// escaped, so the formatted fields of spans can be converted with the rest of
+/// the line.
+pub struct DahliaFields {
+    marker: char,
+    style: String,
+}
+
+impl Default for DahliaFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DahliaFormat {
+    /// Creates a format with a colored level prefix, a gray target, bold span
+    /// names and italic field names.
+    pub fn new() -> Self {
+        Self {
+            dahlia: Dahlia::new(Depth::try_infer_for(&io::stdout()), true, '&'),
+            templates: [
+                "&c&lERROR&R",
+                "&e&lWARN&R",
+                "&aINFO&R",
+                "&bDEBUG&R",
+                "&8TRACE&R",
+            ]
+            .map(str::to_owned),
+            target_style: "&8".to_owned(),
+            span_style: "&l".to_owned(),
+            field_style: "&o".to_owned(),
+        }
+    }
+
+    pub fn with_template(mut self, level: Level, template: impl Into<String>) -> Self {
+        self.set_template(level, template);
+        self
+    }
+
+    pub fn with_target_style(mut self, style: impl Into<String>) -> Self {
+        self.set_target_style(style);
+        self
+    }
+
+    pub fn with_span_style(mut self, style: impl Into<String>) -> Self {
+        self.set_span_style(style);
+        self
+    }
+
+    pub fn with_field_style(mut self, style: impl Into<String>) -> Self {
+        self.set_field_style(style);
+        self
+    }
+
+    pub fn with_depth(mut self, depth: Depth) -> Self {
+        self.set_depth(depth);
+        self
+    }
+
+    pub fn with_dahlia(mut self, dahlia: Dahlia) -> Self {
+        self.set_dahlia(dahlia);
+        self
+    }
+
+    /// Sets the prefix of the events of a level, written with Dahlia codes.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::DahliaFormat;
+    /// use tracing::Level;
+    ///
+    /// let mut format = DahliaFormat::new();
+    /// format.set_template(Level::WARN, "&6warning:&R");
+    /// ```
+    pub fn set_template(&mut self, level: Level, template: impl Into<String>) {
+        self.templates[level_index(level)] = template.into();
+    }
+
+    /// Sets the codes the target of an event is written with, e.g. `"&8"`.
+    /// The formatting is reset after the target.
+    pub fn set_target_style(&mut self, style: impl Into<String>) {
+        self.target_style = style.into();
+    }
+
+    /// Sets the codes span names are written with, e.g. `"&l"`.
+    /// The formatting is reset after every name.
+    pub fn set_span_style(&mut self, style: impl Into<String>) {
+        self.span_style = style.into();
+    }
+
+    /// Sets the codes field names are written with, e.g. `"&o"`.
+    /// The formatting is reset before the value.
+    pub fn set_field_style(&mut self, style: impl Into<String>) {
+        self.field_style = style.into();
+    }
+
+    /// Sets the color depth instead of inferring it for stdout, e.g. when the
+    /// layer writes somewhere else. Colors are still left out when the layer
+    /// has ANSI escapes turned off.
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.dahlia.depth = Some(depth);
+    }
+
+    /// Sets the `Dahlia` used to convert the events, e.g. for a different
+    /// marker, theme or custom codes. Its color depth is replaced by the one
+    /// of the format.
+    pub fn set_dahlia(&mut self, dahlia: Dahlia) {
+        let depth = self.dahlia.depth;
+        self.dahlia = dahlia;
+        self.dahlia.depth = depth;
+    }
+
+    /// Returns the [`FormatFields`] implementation to pair this format with,
+    /// with the same marker and field style.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::DahliaFormat;
+    /// use tracing_subscriber::{fmt, prelude::*};
+    ///
+    /// let format = DahliaFormat::new();
+    /// let layer = fmt::layer()
+    ///     .with_writer(std::io::stderr)
+    ///     .fmt_fields(format.fields())
+    ///     .event_format(format);
+    ///
+    /// tracing_subscriber::registry().with(layer).init();
+    /// ```
+    pub fn fields(&self) -> DahliaFields {
+        DahliaFields {
+            marker: self.dahlia.marker,
+            style: self.field_style.clone(),
+        }
+    }
+
+    /// Returns a `tracing-subscriber` layer writing events to stdout with
+    /// this format.
+    pub fn layer<S>(self) -> Layer<S, DahliaFields, Self>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        Layer::default()
+            .fmt_fields(self.fields())
+            .event_format(self)
+    }
+
+    // the line in Dahlia codes, before it is converted
+    fn markup<S>(&self, ctx: &FmtContext<'_, S, DahliaFields>, event: &Event<'_>) -> String
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let marker = self.dahlia.marker;
+        let metadata = event.metadata();
+        let mut line = self.templates[level_index(*metadata.level())].clone();
+        line.push(' ');
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let _ = write!(
+                    line,
+                    "{}{}{marker}R",
+                    self.span_style,
+                    escape_markers(span.name(), marker)
+                );
+
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<DahliaFields>>() {
+                    if !fields.is_empty() {
+                        let _ = write!(line, "{{{fields}}}");
+                    }
+                }
+                line.push(':');
+            }
+            line.push(' ');
+        }
+
+        let _ = write!(
+            line,
+            "{}{}{marker}R: ",
+            self.target_style,
+            escape_markers(metadata.target(), marker)
+        );
+
+        event.record(&mut FieldVisitor {
+            line: &mut line,
+            fields: &self.fields(),
+            empty: true,
+        });
+
+        line
+    }
+}
+
+impl<S> FormatEvent<S, DahliaFields> for DahliaFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, DahliaFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let markup = self.markup(ctx, event);
+
+        if writer.has_ansi_escapes() {
+            writeln!(writer, "{}", self.dahlia.convert(&markup))
+        } else {
+            writeln!(writer, "{}", self.dahlia.clean(&markup))
+        }
+    }
+}
+
+impl<'writer> FormatFields<'writer> for DahliaFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut line = String::new();
+        fields.record(&mut FieldVisitor {
+            line: &mut line,
+            fields: self,
+            empty: true,
+        });
+        writer.write_str(&line)
+    }
+}
+
+struct FieldVisitor<'a> {
+    line: &'a mut String,
+    fields: &'a DahliaFields,
+    empty: bool,
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.empty {
+            self.line.push(' ');
+        }
+        self.empty = false;
+
+        let marker = self.fields.marker;
+        // the message is the only field whose codes are converted
+        let _ = if field.name() == "message" {
+            write!(self.line, "{value:?}")
+        } else {
+            write!(
+                self.line,
+                "{}{}{marker}R={}",
+                self.fields.style,
+                escape_markers(field.name(), marker),
+                escape_markers(&format!("{value:?}"), marker)
+            )
+        };
+    }
+}
+
+fn level_index(level: Level) -> usize {
+    match level {
+        Level::ERROR => 0,
+        Level::WARN => 1,
+        Level::INFO => 2,
+        Level::DEBUG => 3,
+        Level::TRACE => 4,
+    }
+}
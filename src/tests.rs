@@ -100,7 +100,33 @@ mod convert {
             (long, "&#ff00ff;", "\x1b[38;2;255;0;255m"),
             (long_without_repeat, "&#f00ffa;", "\x1b[38;2;240;15;250m"),
         ],
-        |input| Dahlia::new(Some(Depth::Low), false, '&').convert(input)
+        |input| Dahlia::new(Some(Depth::High), false, '&').convert(input)
+    }
+
+    parametric_test! {
+        downsamples_hex,
+        [
+            (medium_exact, (Depth::Medium, "&#ff5f00;"), "\x1b[38;5;202m"),
+            (medium_near, (Depth::Medium, "&~#fe5e01;"), "\x1b[48;5;202m"),
+            (medium_gray, (Depth::Medium, "&#777;"), "\x1b[38;5;243m"),
+            (low_exact, (Depth::Low, "&#55ff55;"), "\x1b[92m"),
+            (low_near, (Depth::Low, "&#f0f;"), "\x1b[95m"),
+            (low_background, (Depth::Low, "&~#a00;"), "\x1b[41m"),
+            (tty, (Depth::Tty, "&#f0f;"), "\x1b[35m"),
+            (tty_background, (Depth::Tty, "&~#eee;"), "\x1b[47m"),
+        ],
+        |(depth, input)| Dahlia::new(Some(depth), false, '&').convert(input)
+    }
+
+    parametric_test! {
+        downsamples_hex_perceptually,
+        [
+            (medium, (Depth::Medium, "&#f0f;"), "\x1b[38;5;201m"),
+            (low, (Depth::Low, "&#808;"), "\x1b[35m"),
+        ],
+        |(depth, input)| Dahlia::new(Some(depth), false, '&')
+            .with_color_distance(ColorDistance::Ciede2000)
+            .convert(input)
     }
 }
 
@@ -120,6 +146,23 @@ mod clean {
 
     }
 
+    parametric_test! {
+        ignores_auto_reset,
+        [
+            (clean, (Some(Depth::Low), "&4red&_"), "red&"),
+            (convert_without_depth, (None::<Depth>, "&4red&_"), "red&"),
+            (plain, (Some(Depth::Low), "plain"), "plain"),
+        ],
+        |(depth, input)| {
+            // without a depth, `convert` is the same as `clean`
+            let dahlia = Dahlia::new(depth, true, '&');
+            match depth {
+                Some(_) => dahlia.clean(input).into_owned(),
+                None => dahlia.convert(input).into_owned(),
+            }
+        }
+    }
+
     parametric_test! {
         handles_weird_marker,
         [
@@ -147,6 +190,18 @@ mod clean {
     }
 }
 
+parametric_test! {
+    ciede2000,
+    [
+        // reference pairs from Sharma, Wu & Dalal (2005)
+        (pair_1, ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485]), 2.0425),
+        (pair_7, ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0]), 2.3669),
+        (pair_17, ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0]), 27.1492),
+        (pair_25, ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387]), 1.2644),
+    ],
+    |(a, b)| (color::ciede2000(a, b) * 10_000.0).round() / 10_000.0
+}
+
 parametric_test! {
     clean_ansi,
     [
@@ -157,3 +212,2406 @@ parametric_test! {
     ],
     clean_ansi
 }
+
+mod parse {
+    use super::*;
+
+    parametric_test! {
+        handles_input,
+        [
+            (empty, ("", '&'), vec![]),
+            (text, ("hello", '&'), vec![(Token::Text("hello"), 0..5)]),
+            (
+                color,
+                ("a&4b", '&'),
+                vec![
+                    (Token::Text("a"), 0..1),
+                    (Token::Color { code: '4', background: false }, 1..3),
+                    (Token::Text("b"), 3..4),
+                ]
+            ),
+            (
+                background,
+                ("&~f", '&'),
+                vec![(Token::Color { code: 'f', background: true }, 0..3)]
+            ),
+            (
+                hex,
+                ("&#f0f;&~#f00ffa;", '&'),
+                vec![
+                    (Token::Hex { rgb: [255, 0, 255], background: false }, 0..6),
+                    (Token::Hex { rgb: [240, 15, 250], background: true }, 6..16),
+                ]
+            ),
+            (
+                formats,
+                ("&l&R&rc&ro", '&'),
+                vec![
+                    (Token::Format(Format::Bold), 0..2),
+                    (Token::Reset(Reset::All), 2..4),
+                    (Token::Reset(Reset::Color), 4..7),
+                    (Token::Reset(Reset::Format(Format::Italic)), 7..10),
+                ]
+            ),
+            (
+                escape,
+                ("&_4x", '&'),
+                vec![(Token::Escape, 0..2), (Token::Text("4x"), 2..4)]
+            ),
+            (
+                unknown,
+                ("&z&r", '&'),
+                vec![(Token::Text("&z&r"), 0..4)]
+            ),
+            (
+                multibyte_marker,
+                ("ä§e§_", '§'),
+                vec![
+                    (Token::Text("ä"), 0..2),
+                    (Token::Color { code: 'e', background: false }, 2..5),
+                    (Token::Escape, 5..8),
+                ]
+            ),
+        ],
+        |(input, marker)| Dahlia::new(Some(Depth::High), false, marker)
+            .parse(input)
+            .map(|Spanned { token, span }| (token, span))
+            .collect::<Vec<_>>()
+    }
+
+    parametric_test! {
+        round_trips,
+        [
+            (plain, "plain text", "plain text"),
+            (codes, "&4&~#abc;&l&rl&_x", "&4&~#aabbcc;&l&rl&_x"),
+        ],
+        |input| Dahlia::default()
+            .parse(input)
+            .map(|spanned| spanned.token.to_markup('&'))
+            .collect::<String>()
+    }
+}
+
+mod html {
+    use super::*;
+
+    parametric_test! {
+        renders_inline,
+        [
+            (plain, "plain", "plain"),
+            (escapes_html, "<a href='x'>&_\"", "&lt;a href=&#39;x&#39;&gt;&amp;&quot;"),
+            (color, "&4red", "<span style=\"color:#aa0000\">red</span>"),
+            (
+                hex_background,
+                "&~#f0f;pink",
+                "<span style=\"background-color:#ff00ff\">pink</span>"
+            ),
+            (
+                formats,
+                "&l&o&n&mx",
+                "<span style=\"font-weight:bold;font-style:italic;text-decoration-line:underline line-through\">x</span>"
+            ),
+            (
+                resets,
+                "&a&la&rlb&Rc",
+                "<span style=\"color:#55ff55;font-weight:bold\">a</span><span style=\"color:#55ff55\">b</span>c"
+            ),
+            (
+                merges_runs,
+                "&aa&ab",
+                "<span style=\"color:#55ff55\">ab</span>"
+            ),
+            (
+                inverse,
+                "&e&ix",
+                "<span style=\"color:Canvas;background-color:#ffff55\">x</span>"
+            ),
+            (escape, "&_4", "&amp;4"),
+            (trailing_codes, "&4", ""),
+        ],
+        |input| Dahlia::default().to_html(input)
+    }
+
+    parametric_test! {
+        renders_classes,
+        [
+            (color, "&4&~fx", "<span class=\"dahlia-4 dahlia-bg-f\">x</span>"),
+            (
+                hex,
+                "&#123456;&kx",
+                "<span class=\"dahlia-blink\" style=\"color:#123456\">x</span>"
+            ),
+            (
+                inverse,
+                "&~2&ix",
+                "<span class=\"dahlia-2\" style=\"background-color:CanvasText\">x</span>"
+            ),
+        ],
+        |input| Dahlia::default().to_html_classes(input)
+    }
+
+    #[test]
+    fn stylesheet_combines_decorations() {
+        let css = html_stylesheet();
+
+        assert!(css.contains(".dahlia-bg-e { background-color: #ffff55; }"));
+        assert!(css.contains(".dahlia-underline { text-decoration-line: underline; }"));
+        assert!(css.contains(
+            ".dahlia-underline.dahlia-strikethrough.dahlia-blink { text-decoration-line: underline line-through blink; }"
+        ));
+    }
+}
+
+parametric_test! {
+    ansi_to_dahlia,
+    [
+        (plain, ("plain", '&'), "plain"),
+        (escapes_marker, ("a&b", '&'), "a&_b"),
+        (basic, ("\x1b[31mred\x1b[0m", '&'), "&4red&R"),
+        (bright_background, ("\x1b[103myellow", '&'), "&~eyellow"),
+        (combined, ("\x1b[1;3;4;9mx\x1b[22;23;24;29m", '&'), "&l&o&n&mx&rl&rj&ro&rn&rm"),
+        (normal_intensity, ("\x1b[2mx\x1b[22my", '&'), "&jx&rl&rjy"),
+        (empty_reset, ("\x1b[mx", '&'), "&Rx"),
+        (color_resets, ("\x1b[39;49m", '&'), "&rf&rb"),
+        (indexed_palette, ("\x1b[38;5;214m", '&'), "&6"),
+        (indexed_system, ("\x1b[48;5;9m", '&'), "&~@9;"),
+        (indexed_same_index, ("\x1b[38;5;0m\x1b[48;5;15m", '&'), "&0&~f"),
+        (indexed_other, ("\x1b[38;5;196m", '&'), "&@196;"),
+        (rgb_palette, ("\x1b[48;2;85;255;85m", '&'), "&~a"),
+        (rgb_other, ("\x1b[38;2;1;2;3m", '&'), "&#010203;"),
+        (colon_rgb, ("\x1b[38:2::1:2:3m\x1b[48:5:0m", '&'), "&#010203;&~0"),
+        (underline_off, ("\x1b[4:0m", '&'), "&rn"),
+        (invalid_color, ("\x1b[38;2;300;0;0m", '&'), ""),
+        (other_escapes, ("\x1b[2Jclear\x1b]0;title\x07", '&'), "clear"),
+        (marker, ("\x1b[92m§", '§'), "§a§_"),
+    ],
+    |(input, marker)| ansi_to_dahlia(input, marker)
+}
+
+parametric_test! {
+    ansi_to_dahlia_round_trips,
+    [
+        (palette, "&4&~a&l&ihello&R"),
+        (hex, "&#123456;&~#fedcba;hex&rc"),
+        (escaped, "a&_b"),
+        (system, "&@1;&~@9;system"),
+    ],
+    |case, input: &str| {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        let ansi = dahlia.convert(input);
+
+        assert_eq!(dahlia.convert(&ansi_to_dahlia(&ansi, '&')), ansi, "case {case}");
+    }
+}
+
+mod minecraft_json {
+    use super::*;
+
+    parametric_test! {
+        renders,
+        [
+            (empty, "", r#"{"text":""}"#),
+            (plain, "plain", r#"{"text":"plain"}"#),
+            (
+                named_color,
+                "&dpink",
+                r#"{"text":"pink","color":"light_purple"}"#
+            ),
+            (hex, "&#f0a;x", r##"{"text":"x","color":"#ff00aa"}"##),
+            (
+                formats,
+                "&l&o&n&m&kx",
+                r#"{"text":"x","bold":true,"italic":true,"underlined":true,"strikethrough":true,"obfuscated":true}"#
+            ),
+            (
+                extra,
+                "a&cb&Rc",
+                r#"{"text":"","extra":[{"text":"a"},{"text":"b","color":"red"},{"text":"c"}]}"#
+            ),
+            (
+                drops_unsupported,
+                "&~4&h&i&ja&7b",
+                r#"{"text":"","extra":[{"text":"a"},{"text":"b","color":"gray"}]}"#
+            ),
+            (merges_runs, "&~1a&~2b", r#"{"text":"ab"}"#),
+            (
+                escapes_json,
+                "\"&_\\\n",
+                r#"{"text":"\"&\\\n"}"#
+            ),
+        ],
+        |input| Dahlia::default().to_minecraft_json(input)
+    }
+}
+
+mod minecraft_legacy {
+    use super::*;
+
+    parametric_test! {
+        exports,
+        [
+            (plain, "plain", "plain"),
+            (color, "&cred", "§cred"),
+            (formats_after_color, "&l&4a&ob", "§4§la§ob"),
+            (repeats_formats, "&l&4a&5b", "§4§la§5§lb"),
+            (format_off, "&n&4a&rnb", "§4§na§4b"),
+            (reset, "&4a&Rb", "§4a§rb"),
+            (reset_format_only, "&la&rlb", "§la§rb"),
+            (hex, "&#123abc;x", "§x§1§2§3§a§b§cx"),
+            (blink, "&kx", "§kx"),
+            (drops_unsupported, "&~4&h&i&jx", "x"),
+            (escape, "&_x", "&x"),
+        ],
+        |input| Dahlia::default().to_minecraft_legacy(input)
+    }
+
+    parametric_test! {
+        imports,
+        [
+            (plain, "plain", "plain"),
+            (color, "§cred", "&cred"),
+            (uppercase, "§C§Lred", "&c&lred"),
+            (resets_formats, "§l§4a§5b", "&l&R&4a&5b"),
+            (reset, "§4a§rb", "&4a&Rb"),
+            (hex, "§x§1§2§3§A§b§cx", "&#123abc;x"),
+            (incomplete_hex, "§x§1§2x", "§x&1&2x"),
+            (obfuscated, "§kx", "&kx"),
+            (unknown, "§z§hx", "§z§hx"),
+            (trailing, "x§", "x§"),
+            (escapes_markers, "§aA&B", "&aA&_B"),
+        ],
+        |input| minecraft_legacy_to_dahlia(input, '&')
+    }
+
+    parametric_test! {
+        imports_with_section_marker,
+        [
+            (color, "§cred", "§cred"),
+            (unknown, "§zfoo §4bar", "§_zfoo §4bar"),
+            (incomplete_hex, "§x§1x", "§_x§1x"),
+            (trailing, "x§", "x§_"),
+        ],
+        |input| minecraft_legacy_to_dahlia(input, '§')
+    }
+
+    #[test]
+    fn section_marker_keeps_unknown_codes() {
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '§');
+        let input = "§zfoo §4bar";
+
+        assert_eq!(
+            dahlia.convert(&minecraft_legacy_to_dahlia(input, '§')),
+            "§zfoo \x1b[31mbar"
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let dahlia = Dahlia::default();
+
+        for input in ["&4&la&5b&rc", "&#ff8800;&o&nx&R y", "&ka&lb&rk&rlc"] {
+            let legacy = dahlia.to_minecraft_legacy(input);
+
+            assert_eq!(
+                dahlia.to_minecraft_legacy(&minecraft_legacy_to_dahlia(&legacy, '&')),
+                legacy,
+                "input {input}"
+            );
+        }
+    }
+}
+
+mod writer {
+    use std::io::Write;
+
+    use super::*;
+
+    const INPUT: &str = "&4red &~#f0f;pink &_4 ä&lbold&#abcdef;&rcX&R§ end";
+
+    /// Checks that the writer matches `convert` wherever the input is split.
+    fn assert_matches_convert(dahlia: &Dahlia, input: &str, case: &str) {
+        let expected = dahlia.convert(input).into_owned();
+
+        // split the input at every possible byte position
+        for split in 0..=input.len() {
+            let (head, tail) = input.as_bytes().split_at(split);
+
+            let mut writer = dahlia.writer(Vec::new());
+            writer.write_all(head).unwrap();
+            writer.write_all(tail).unwrap();
+            let output = writer.finish().unwrap();
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                expected,
+                "case {case} (split at {split})"
+            );
+        }
+
+        let mut writer = dahlia.writer(Vec::new());
+        for byte in input.as_bytes() {
+            writer.write_all(&[*byte]).unwrap();
+        }
+        let output = writer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            expected,
+            "case {case} (byte by byte)"
+        );
+    }
+
+    parametric_test! {
+        matches_convert,
+        [
+            (high, Depth::High),
+            (medium, Depth::Medium),
+            (tty, Depth::Tty),
+        ],
+        |case, depth| assert_matches_convert(&Dahlia::new(Some(depth), true, '&'), INPUT, case)
+    }
+
+    parametric_test! {
+        matches_convert_with_codes,
+        [
+            (
+                dahlia1_hex,
+                (
+                    Dahlia::new(Some(Depth::Low), false, '&').with_spec_version(SpecVersion::Dahlia1),
+                    "&~[#ff5555]x &[#123456]y&rz"
+                )
+            ),
+            (
+                named_colors,
+                (
+                    Dahlia::new(Some(Depth::Low), false, '&').with_named_colors(true),
+                    "&~[light_red]x &[tomato]y &[nope]z"
+                )
+            ),
+            (
+                definitions,
+                (
+                    {
+                        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+                        dahlia.define("error", "&c&l").unwrap();
+                        dahlia.define("p", "&~1").unwrap();
+                        dahlia
+                    },
+                    "&[error]x &py &[eror]z"
+                )
+            ),
+            (
+                gradients,
+                (
+                    Dahlia::new(Some(Depth::High), true, '&'),
+                    "a &[gradient:#f00:#00f]grad&rf ok &~[rainbow]x &[rainbow]y&R&[rainbow]z"
+                )
+            ),
+            (
+                gradient_stops,
+                (
+                    Dahlia::new(Some(Depth::High), false, '&'),
+                    "&[gradient:#ff0000:#00ff00:#0000ff]a&_b c&ld&~[gradient:#000:#fff]e f&[rainbow]gh&4i"
+                )
+            ),
+            (
+                gradient_graphemes,
+                (
+                    Dahlia::new(Some(Depth::Medium), false, '§'),
+                    "§[rainbow]e\u{301}日本§#abc;x§[gradient:#f00:#00f]👍🏽ä"
+                )
+            ),
+            (
+                gradients_ended_by_definitions,
+                (
+                    {
+                        let mut dahlia = Dahlia::new(Some(Depth::High), false, '&');
+                        dahlia.define("p", "&4&l").unwrap();
+                        dahlia
+                    },
+                    "&[gradient:#f00:#00f]ab&pc &[rainbow]de&[p]f"
+                )
+            ),
+            (
+                links,
+                (
+                    Dahlia::new(Some(Depth::Low), false, '&'),
+                    "x&[link:https://example.com/a/long/path?a=1&b=2&4]y&[/link] &[link:u]&[rainbow]ab&[/link]c"
+                )
+            ),
+            (
+                links_with_wide_marker,
+                (
+                    Dahlia::new(Some(Depth::Low), false, '§'),
+                    "§[link:https://x.io/?a=§b]§4x§[/link]§[link:"
+                )
+            ),
+            (
+                underlines,
+                (
+                    Dahlia::new(Some(Depth::High), false, '&'),
+                    "x&^#abc;y&r^ &t&^cu&rt &w&^#112233;v&rw&r^&^"
+                )
+            ),
+            (
+                xterm,
+                (
+                    Dahlia::new(Some(Depth::Medium), false, '&'),
+                    "x&~@208;y&@25;z&^@7;w&@256; &@07; &~@"
+                )
+            ),
+        ],
+        |case, (dahlia, input): (Dahlia, &str)| assert_matches_convert(&dahlia, input, case)
+    }
+
+    parametric_test! {
+        matches_convert_with_code_markers,
+        [
+            (underscore, ('_', SpecVersion::Dahlia2)),
+            (underscore_dahlia1, ('_', SpecVersion::Dahlia1)),
+            (r, ('r', SpecVersion::Dahlia2)),
+            (r_dahlia1, ('r', SpecVersion::Dahlia1)),
+        ],
+        |case, (marker, spec)| {
+            let dahlia = Dahlia::new(Some(Depth::Low), true, marker).with_spec_version(spec);
+
+            for input in ["__", "_/linkerror 0", "a__rb_r_rrf_[link:x]_[/link]r"] {
+                let expected = dahlia.convert(input).into_owned();
+                assert_matches_convert(&dahlia, input, case);
+
+                for chunk_size in 2..=3 {
+                    let mut writer = dahlia.writer(Vec::new());
+                    for chunk in input.as_bytes().chunks(chunk_size) {
+                        writer.write_all(chunk).unwrap();
+                    }
+                    let output = writer.finish().unwrap();
+
+                    assert_eq!(
+                        String::from_utf8(output).unwrap(),
+                        expected,
+                        "case {case} ({input:?} in chunks of {chunk_size})"
+                    );
+                }
+            }
+        }
+    }
+
+    parametric_test! {
+        handles_chunks,
+        [
+            (byte_by_byte, ("&#ff00ff;x", 1), "\x1b[95mx\x1b[0m"),
+            (unfinished_code, ("&#ff", 2), "&#ff\x1b[0m"),
+            (trailing_marker, ("x&", 1), "x&\x1b[0m"),
+            (trailing_reset, ("&4x&R", 1), "\x1b[31mx\x1b[0m"),
+        ],
+        |(input, chunk_size): (&str, usize)| {
+            let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+            let mut writer = dahlia.writer(Vec::new());
+
+            for chunk in input.as_bytes().chunks(chunk_size) {
+                writer.write_all(chunk).unwrap();
+            }
+
+            String::from_utf8(writer.finish().unwrap()).unwrap()
+        }
+    }
+
+    #[test]
+    fn keeps_styling_across_flush() {
+        let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+        let mut writer = dahlia.writer(Vec::new());
+
+        writer.write_all(b"&4a").unwrap();
+        writer.flush().unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"\x1b[31ma");
+
+        writer.write_all(b"b&lc").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"\x1b[31mab\x1b[1mc");
+
+        assert_eq!(
+            writer.finish().unwrap(),
+            dahlia.convert("&4ab&lc").as_bytes()
+        );
+    }
+
+    #[test]
+    fn resets_without_input() {
+        let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+
+        let output = dahlia.writer(Vec::new()).finish().unwrap();
+
+        assert_eq!(output, dahlia.convert("").as_bytes());
+        assert_eq!(output, b"\x1b[0m");
+    }
+
+    #[test]
+    fn resets_on_drop() {
+        let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+        let mut output = Vec::new();
+
+        {
+            let mut writer = dahlia.writer(&mut output);
+            writer.write_all(b"&2b&").unwrap();
+        }
+
+        assert_eq!(output, b"\x1b[32mb&\x1b[0m");
+    }
+
+    #[test]
+    fn passes_invalid_utf8_through() {
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        let mut writer = dahlia.writer(Vec::new());
+
+        writer.write_all(b"&4\xff&2\xe4").unwrap();
+
+        assert_eq!(writer.finish().unwrap(), b"\x1b[31m\xff\x1b[32m\xe4");
+    }
+
+    /// Fails the given number of writes, then writes everything.
+    struct Failing {
+        written: Vec<u8>,
+        failures: usize,
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::other("failed"));
+            }
+            // only part of the buffer, so errors can happen in the middle
+            let len = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    parametric_test! {
+        retries_failed_writes,
+        [
+            (once, 1),
+            (twice, 2),
+            (five_times, 5),
+        ],
+        |case, failures| {
+            let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+            let chunks = ["&4red", " &2gr", "een&R", " &lbold"];
+            let inner = Failing { written: Vec::new(), failures };
+
+            let mut writer = dahlia.writer(inner);
+            for chunk in chunks {
+                while writer.write_all(chunk.as_bytes()).is_err() {}
+            }
+            while writer.flush().is_err() {}
+
+            let expected = dahlia.convert(&chunks.concat()).into_owned();
+            let written = writer.finish().unwrap().written;
+            assert_eq!(String::from_utf8(written).unwrap(), expected, "case {case}");
+        }
+    }
+
+    #[test]
+    fn returns_errors_before_taking_input() {
+        let dahlia = Dahlia::new(Some(Depth::Low), true, '&');
+        let inner = Failing {
+            written: Vec::new(),
+            failures: 2,
+        };
+        let mut writer = dahlia.writer(inner);
+
+        // the first error is kept for the next call, as `a` is buffered
+        assert_eq!(writer.write(b"&4a").unwrap(), 3);
+        assert!(writer.write(b"&2b").is_err());
+        assert_eq!(writer.get_ref().written, b"");
+
+        assert_eq!(writer.finish().unwrap().written, b"\x1b[31ma\x1b[0m");
+    }
+}
+
+mod validate {
+    use super::*;
+
+    parametric_test! {
+        finds_invalid_codes,
+        [
+            (valid, "&4&~#abc;&l&rl&R&_x", Vec::<&str>::new()),
+            (unknown, "a&zb", vec!["invalid code `&z` at byte 1: unknown code, use `&_` for a literal `&`"]),
+            (trailing, "a&", vec!["invalid code `&` at byte 1: the string ends with a marker, use `&_` for a literal `&`"]),
+            (
+                uppercase,
+                "&A&L",
+                vec![
+                    "invalid code `&A` at byte 0: codes are case-sensitive, did you mean `&a`?",
+                    "invalid code `&L` at byte 2: codes are case-sensitive, did you mean `&l`?",
+                ]
+            ),
+            (old_blink, "&px", vec!["invalid code `&p` at byte 0: blink is `&k` since Dahlia 2.0"]),
+            (
+                old_hex,
+                "&[#ffaff3]",
+                vec!["invalid code `&[` at byte 0: hex colors are written as `&#rrggbb;` since Dahlia 2.0"]
+            ),
+            (
+                name_not_enabled,
+                "&[red]",
+                vec!["invalid code `&[red]` at byte 0: named colors aren't enabled (see `Dahlia::set_named_colors`) and no code is defined with this name"]
+            ),
+            (unknown_bracket, "&[ x", vec!["invalid code `&[` at byte 0: unknown code, use `&_` for a literal `&`"]),
+            (
+                reset_suffix,
+                "&r &rz",
+                vec![
+                    "invalid code `&r` at byte 0: reset codes are `R` or `r` followed by one of b, c, f, h-o, s-w or `^`",
+                    "invalid code `&rz` at byte 3: reset codes are `R` or `r` followed by one of b, c, f, h-o, s-w or `^`",
+                ]
+            ),
+            (
+                hex_unterminated,
+                "&#fff x",
+                vec!["invalid code `&#fff` at byte 0: hex colors end with `;`, did you mean `&#fff;`?"]
+            ),
+            (
+                hex_length,
+                "&#12345;",
+                vec!["invalid code `&#12345;` at byte 0: hex colors need exactly 3 or 6 digits"]
+            ),
+            (
+                hex_digits,
+                "&~#ggg;",
+                vec!["invalid code `&~#ggg;` at byte 0: hex colors can only contain the digits 0-9 and a-f"]
+            ),
+            (
+                hex_uppercase,
+                "&~#ABC;",
+                vec!["invalid code `&~#ABC;` at byte 0: hex digits must be lowercase, did you mean `&~#abc;`?"]
+            ),
+            (
+                gradient_stops,
+                "&[gradient:#f00]x",
+                vec![
+                    "invalid code `&[gradient:#f00]` at byte 0: gradients are written as `&[gradient:#rrggbb:#rrggbb]`, \
+                     with two or more lowercase hex colors, or `&[rainbow]`"
+                ]
+            ),
+            (
+                gradient_unclosed,
+                "&~[rainbow x",
+                vec![
+                    "invalid code `&~[rainbow` at byte 0: gradients are written as `&[gradient:#rrggbb:#rrggbb]`, \
+                     with two or more lowercase hex colors, or `&[rainbow]`"
+                ]
+            ),
+            (
+                gradient_uppercase,
+                "&[gradient:#F00:#00f]",
+                vec![
+                    "invalid code `&[gradient:#F00:#00f]` at byte 0: gradients are written as \
+                     `&[gradient:#rrggbb:#rrggbb]`, with two or more lowercase hex colors, or `&[rainbow]`"
+                ]
+            ),
+            (link_code_in_url, "&[link:https://x.io/?a=1&b=2&_]x&[/link]", Vec::<&str>::new()),
+            (
+                link_empty_url,
+                "&[link:]x",
+                vec![
+                    "invalid code `&[link:]` at byte 0: links are written as `&[link:url]text&[/link]`, \
+                     URLs can't contain whitespace or `]`"
+                ]
+            ),
+            (
+                link_whitespace,
+                "&[link: x]",
+                vec![
+                    "invalid code `&[link:` at byte 0: links are written as `&[link:url]text&[/link]`, \
+                     URLs can't contain whitespace or `]`"
+                ]
+            ),
+            (
+                underline_color,
+                "&^z",
+                vec![
+                    "invalid code `&^z` at byte 0: `^` must be followed by a color code (0-9, a-f), \
+                     a hex color or an xterm-256 color"
+                ]
+            ),
+            (underline_hex, "&^#12;", vec!["invalid code `&^#12;` at byte 0: hex colors need exactly 3 or 6 digits"]),
+            (underline_uppercase, "&T", vec!["invalid code `&T` at byte 0: codes are case-sensitive, did you mean `&t`?"]),
+            (
+                xterm_range,
+                "&@256;",
+                vec!["invalid code `&@256;` at byte 0: xterm-256 colors are written as `&@n;`, with n from 0 to 255"]
+            ),
+            (
+                xterm_unterminated,
+                "&@12 x",
+                vec!["invalid code `&@12` at byte 0: xterm-256 colors end with `;`, did you mean `&@12;`?"]
+            ),
+            (
+                xterm_leading_zero,
+                "&~@007;",
+                vec!["invalid code `&~@007;` at byte 0: indices are written without leading zeros, did you mean `&~@7;`?"]
+            ),
+            (
+                xterm_underline,
+                "&^@x",
+                vec!["invalid code `&^@x` at byte 0: xterm-256 colors are written as `&^@n;`, with n from 0 to 255"]
+            ),
+            (
+                background,
+                "&~z",
+                vec!["invalid code `&~z` at byte 0: `~` must be followed by a color code (0-9, a-f), a hex color or an xterm-256 color"]
+            ),
+        ],
+        |input| Dahlia::default()
+            .validate(input)
+            .map_or_else(|error| error.codes, |()| vec![])
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn try_convert_matches_convert() {
+        let dahlia = Dahlia::new(Some(Depth::Medium), true, '§');
+        let input = "§e§lHi §_z";
+
+        assert_eq!(dahlia.try_convert(input).unwrap(), dahlia.convert(input));
+        assert!(dahlia.try_convert("§z").is_err());
+    }
+}
+
+parametric_test! {
+    display_width,
+    [
+        (plain, ("hello", '&'), 5),
+        (codes, ("&4&l&~#abc;hi&R&rc", '&'), 2),
+        (escape, ("&_4", '&'), 2),
+        (wide_marker, ("§_§4x", '§'), 2),
+        (cjk, ("&e日本語", '&'), 6),
+        (combining, ("e\u{301}&lx", '&'), 2),
+        (emoji, ("&c👍🏽 👨\u{200d}👩\u{200d}👧", '&'), 5),
+        (unknown_code, ("&z", '&'), 2),
+        (raw_ansi, ("\x1b[1m&2ok", '&'), 2),
+    ],
+    |(input, marker)| Dahlia::new(Some(Depth::High), false, marker).display_width(input)
+}
+
+parametric_test! {
+    ansi_display_width,
+    [
+        (plain, "hello", 5),
+        (colored, "\x1b[38;2;255;255;85mhi\x1b[0m", 2),
+        (wide, "\x1b[31m日本\x1b[0m", 4),
+    ],
+    ansi_display_width
+}
+
+mod dahlia1 {
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (hex, "&[#ffaff3]x", "\x1b[38;2;255;175;243mx"),
+            (hex_uppercase, "&~[#FFAFF3]x", "\x1b[48;2;255;175;243mx"),
+            (reset, "&4a&rb", "\x1b[38;2;170;0;0ma\x1b[0mb"),
+            (hidden, "&kx", "\x1b[8mx"),
+            (blink, "&px", "\x1b[5mx"),
+            (gold, "&gx", "\x1b[38;2;221;214;5mx"),
+            (unchanged, "&l&n&ox", "\x1b[1m\x1b[4m\x1b[3mx"),
+            (v2_codes_are_text, "&#fff;&R&h", "&#fff;&R&h"),
+            (escape, "&_4", "&4"),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&')
+            .with_spec_version(SpecVersion::Dahlia1)
+            .convert(input)
+    }
+
+    parametric_test! {
+        migrates,
+        [
+            (plain, "plain", "plain"),
+            (hex, "&[#ABCDEF]&~[#123456]x", "&#abcdef;&~#123456;x"),
+            (formats, "&k&p&l&rx", "&h&k&l&Rx"),
+            (gold, "&~gx", "&~#ddd605;x"),
+            (literal_markers, "&z&R&#fff;", "&_z&_R&_#fff;"),
+            (escape, "&_", "&_"),
+        ],
+        |input| migrate_from_dahlia1(input, '&')
+    }
+
+    #[test]
+    fn migration_keeps_output() {
+        let input = "&[#ffaff3]&kx&r&~ey&pz&gw";
+        let migrated = migrate_from_dahlia1(input, '&');
+
+        assert_eq!(
+            Dahlia::new(Some(Depth::High), false, '&').convert(&migrated),
+            Dahlia::new(Some(Depth::High), false, '&')
+                .with_spec_version(SpecVersion::Dahlia1)
+                .convert(input)
+        );
+    }
+
+    parametric_test! {
+        wraps,
+        [
+            (formats, ("&~4&lhello world&r again", 7), vec!["&~4&lhello&r", "&~4&lworld&r", "again"]),
+            (hex, ("&[#FF0000]&kab cd", 2), vec!["&[#ff0000]&kab&r", "&[#ff0000]&kcd&r"]),
+            (blink_and_gold, ("&p&~gab cd", 2), vec!["&p&~[#ddd605]ab&r", "&~[#ddd605]&pcd&r"]),
+        ],
+        |(input, width)| Dahlia::new(Some(Depth::Low), false, '&')
+            .with_spec_version(SpecVersion::Dahlia1)
+            .wrap(input, width)
+    }
+
+    #[test]
+    fn truncates() {
+        let dahlia =
+            Dahlia::new(Some(Depth::Low), false, '&').with_spec_version(SpecVersion::Dahlia1);
+        let truncated = dahlia.truncate("&[#ff0000]&lvery long name", 8, "…");
+
+        assert_eq!(truncated, "&[#ff0000]&lvery lo…&r");
+        assert_eq!(
+            dahlia.convert(&truncated),
+            dahlia.convert("&[#ff0000]&lvery lo…&r")
+        );
+        assert!(!dahlia.clean(&truncated).contains('&'));
+    }
+
+    #[test]
+    fn wraps_codes_without_equivalent() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("sky", "&@117;&^4&t&w").unwrap();
+        dahlia.set_spec_version(SpecVersion::Dahlia1);
+
+        assert_eq!(
+            dahlia.wrap("&[sky]ab cd", 2),
+            ["&[sky]ab&r", "&[#87d7ff]&ncd&r"]
+        );
+    }
+
+    #[test]
+    fn validates() {
+        let error = Dahlia::new(Some(Depth::High), false, '&')
+            .with_spec_version(SpecVersion::Dahlia1)
+            .validate("&4&R&[#fff]")
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid code `&R` at byte 2: unknown code, use `&_` for a literal `&`\n\
+             invalid code `&[#fff]` at byte 4: hex colors are written as `&[#rrggbb]` in Dahlia 1.x"
+        );
+    }
+
+    #[test]
+    fn marker_keeps_spec_version() {
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '&')
+            .with_spec_version(SpecVersion::Dahlia1)
+            .with_marker('%');
+
+        assert_eq!(dahlia.convert("%k%[#ffffff]x"), "\x1b[8m\x1b[97mx");
+    }
+}
+
+mod named_colors {
+    use super::*;
+
+    parametric_test! {
+        parses,
+        [
+            (palette, "&[red]", Token::Color { code: '4', background: false }),
+            (light, "&[light_blue]", Token::Color { code: '9', background: false }),
+            (background, "&~[white]", Token::Color { code: 'f', background: true }),
+            (css, "&[tomato]", Token::Hex { rgb: [255, 99, 71], background: false }),
+            (css_background, "&~[rebeccapurple]", Token::Hex { rgb: [102, 51, 153], background: true }),
+            (css_light, "&[lightblue]", Token::Hex { rgb: [173, 216, 230], background: false }),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&')
+            .with_named_colors(true)
+            .parse(input)
+            .next()
+            .unwrap()
+            .token
+    }
+
+    #[test]
+    fn converts_like_codes() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&').with_named_colors(true);
+
+        for (named, code) in [
+            ("&[red]x", "&4x"),
+            ("&[light_red]x", "&cx"),
+            ("&~[light_gray]&[gray]x", "&~7&8x"),
+            ("&[orangered]x", "&#ff4500;x"),
+        ] {
+            assert_eq!(dahlia.convert(named), dahlia.convert(code), "{named}");
+        }
+        assert_eq!(dahlia.convert("&[nope]x"), "&[nope]x");
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        assert_eq!(dahlia.convert("&[red]"), "&[red]");
+    }
+
+    #[test]
+    fn validates_names() {
+        let error = Dahlia::new(Some(Depth::High), false, '&')
+            .with_named_colors(true)
+            .validate("&[red]&~[redd]&[#fff]")
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid code `&~[redd]` at byte 6: unknown color name, use a palette or CSS color name\n\
+             invalid code `&[` at byte 14: hex colors are written as `&#rrggbb;` since Dahlia 2.0"
+        );
+    }
+}
+
+mod definitions {
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (bracketed, "&[error]x", "\x1b[91m\x1b[1mx"),
+            (single_char, "&px", "\x1b[44mx"),
+            (sgr, "a&[over-line]b", "a\x1b[53mb"),
+            (unknown, "&[warning]x", "&[warning]x"),
+            (builtin, "&4x", "\x1b[31mx"),
+        ],
+        |input| {
+            let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+            dahlia.define("error", "&c&l").unwrap();
+            dahlia.define("p", "&~1").unwrap();
+            dahlia.define_sgr("over-line", "53").unwrap();
+            dahlia.convert(input)
+        }
+    }
+
+    parametric_test! {
+        parses,
+        [
+            (
+                expands,
+                "a&[error]b",
+                vec![
+                    Spanned { token: Token::Text("a"), span: 0..1 },
+                    Spanned { token: Token::Color { code: 'c', background: false }, span: 1..9 },
+                    Spanned { token: Token::Format(Format::Bold), span: 1..9 },
+                    Spanned { token: Token::Text("b"), span: 9..10 },
+                ]
+            ),
+            (
+                skips_sgr,
+                "a&[over-line]b",
+                vec![
+                    Spanned { token: Token::Text("a"), span: 0..1 },
+                    Spanned { token: Token::Text("b"), span: 13..14 },
+                ]
+            ),
+        ],
+        |input| {
+            let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+            dahlia.define("error", "&c&l").unwrap();
+            dahlia.define_sgr("over-line", "53").unwrap();
+            dahlia.parse(input).collect::<Vec<_>>()
+        }
+    }
+
+    #[test]
+    fn nested_definitions() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        dahlia.define_sgr("over-line", "53").unwrap();
+        dahlia.define("fatal", "&[error]&n&[over-line]").unwrap();
+
+        assert_eq!(
+            dahlia.convert("&[fatal]x"),
+            "\x1b[91m\x1b[1m\x1b[4m\x1b[53mx"
+        );
+    }
+
+    parametric_test! {
+        rejects_builtin_names,
+        [
+            (color, ("4", SpecVersion::Dahlia2), "invalid code `&4` at byte 0: `4` is a built-in code or starts one, and can't be redefined"),
+            (reset_prefix, ("r", SpecVersion::Dahlia2), "invalid code `&r` at byte 0: `r` is a built-in code or starts one, and can't be redefined"),
+            (full_reset, ("R", SpecVersion::Dahlia2), "invalid code `&R` at byte 0: `R` is a built-in code or starts one, and can't be redefined"),
+            (format, ("w", SpecVersion::Dahlia2), "invalid code `&w` at byte 0: `w` is a built-in code or starts one, and can't be redefined"),
+            (escape, ("_", SpecVersion::Dahlia2), "invalid code `&_` at byte 0: `_` is a built-in code or starts one, and can't be redefined"),
+            (dahlia1_blink, ("p", SpecVersion::Dahlia1), "invalid code `&p` at byte 0: `p` is a built-in code or starts one, and can't be redefined"),
+            (rainbow, ("rainbow", SpecVersion::Dahlia2), "invalid code `&[rainbow]` at byte 0: `rainbow` is a built-in code and can't be redefined"),
+            (
+                color_name,
+                ("light_red", SpecVersion::Dahlia2),
+                "invalid code `&[light_red]` at byte 0: `light_red` is a color name (see `Dahlia::set_named_colors`) and can't be redefined"
+            ),
+        ],
+        |(name, spec)| {
+            let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&').with_spec_version(spec);
+            let error = dahlia.define(name, "&l").unwrap_err().to_string();
+            assert_eq!(dahlia.define_sgr(name, "1").unwrap_err().to_string(), error);
+            error
+        }
+    }
+
+    #[test]
+    fn spec_version_removes_hiding_names() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        dahlia.define("p", "&~1").unwrap();
+        dahlia.set_spec_version(SpecVersion::Dahlia1);
+
+        assert_eq!(dahlia.convert("&px&[error]y"), "\x1b[5mx\x1b[91m\x1b[1my");
+    }
+
+    #[test]
+    fn try_spec_version_rejects_hiding_names() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("p", "&~1").unwrap();
+        dahlia.define("g", "&l").unwrap();
+
+        assert_eq!(
+            dahlia.try_set_spec_version(SpecVersion::Dahlia1).unwrap_err().to_string(),
+            "invalid code `&g` at byte 0: `g` is a built-in code or starts one, and can't be redefined\n\
+             invalid code `&p` at byte 0: `p` is a built-in code or starts one, and can't be redefined"
+        );
+        assert_eq!(dahlia.convert("&px&gy"), "\x1b[44mx\x1b[1my");
+    }
+
+    #[test]
+    fn builtin_codes_keep_working() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        assert!(dahlia.define("r", "&l").is_err());
+
+        assert_eq!(dahlia.convert("&4x&rfy"), "\x1b[31mx\x1b[39my");
+    }
+
+    #[test]
+    fn redefines() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        dahlia.define("error", "&4").unwrap();
+
+        assert_eq!(dahlia.convert("&[error]x"), "\x1b[31mx");
+    }
+
+    #[test]
+    fn rejects_invalid_markup() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+
+        assert_eq!(
+            dahlia.define("a1", "&c&z").unwrap_err().to_string(),
+            "invalid code `&z` at byte 2: unknown code, use `&_` for a literal `&`"
+        );
+        assert_eq!(
+            dahlia.define("a2", "&cred").unwrap_err().to_string(),
+            "invalid code `red` at byte 2: definitions can only contain codes"
+        );
+        assert!(dahlia.define("a3", "&_").is_err());
+        assert_eq!(dahlia.convert("&[a1]&[a2]&[a3]"), "&[a1]&[a2]&[a3]");
+    }
+
+    parametric_test! {
+        rejects_unsupported_codes,
+        [
+            (gradient, "&l&[gradient:#f00:#00f]", "invalid code `&[gradient:#f00:#00f]` at byte 2: definitions can't contain gradients"),
+            (rainbow, "&l&~[rainbow]", "invalid code `&~[rainbow]` at byte 2: definitions can't contain gradients"),
+            (link, "&[link:https://docs.rs]", "invalid code `&[link:https://docs.rs]` at byte 0: definitions can't contain links"),
+            (link_end, "&l&[/link]", "invalid code `&[/link]` at byte 2: definitions can't contain links"),
+        ],
+        |markup| Dahlia::new(Some(Depth::Low), false, '&')
+            .define("banner", markup)
+            .unwrap_err()
+            .to_string()
+    }
+
+    parametric_test! {
+        rejects_invalid_names,
+        [
+            (empty, "", "invalid code `&[]` at byte 0: names of defined codes must be 1 to 20 characters long"),
+            (
+                too_long,
+                "a-name-that-is-too-long",
+                "invalid code `&[a-name-that-is-too-long]` at byte 0: names of defined codes must be 1 to 20 characters long"
+            ),
+            (
+                whitespace,
+                "two words",
+                "invalid code `&[two words]` at byte 0: names of defined codes can only contain ASCII letters, digits, `_` and `-`"
+            ),
+        ],
+        |name| Dahlia::new(Some(Depth::Low), false, '&')
+            .define_sgr(name, "1")
+            .unwrap_err()
+            .to_string()
+    }
+
+    parametric_test! {
+        rejects_invalid_sgr,
+        [
+            (
+                escape_sequence,
+                "1m\x1b]0;pwned\x07",
+                "invalid code `1m\x1b]0;pwned\x07` at byte 0: SGR parameters are numbers separated by `;` or `:`, e.g. `6` or `4:3`"
+            ),
+            (
+                trailing_m,
+                "1m",
+                "invalid code `1m` at byte 0: SGR parameters are numbers separated by `;` or `:`, e.g. `6` or `4:3`"
+            ),
+            (
+                empty,
+                "",
+                "invalid code `` at byte 0: SGR parameters are numbers separated by `;` or `:`, e.g. `6` or `4:3`"
+            ),
+            (
+                empty_param,
+                "1;;2",
+                "invalid code `1;;2` at byte 0: SGR parameters are numbers separated by `;` or `:`, e.g. `6` or `4:3`"
+            ),
+        ],
+        |params| {
+            let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+            let error = dahlia.define_sgr("x", params).unwrap_err().to_string();
+            assert_eq!(dahlia.convert("&xhi"), "&xhi");
+            error
+        }
+    }
+
+    #[test]
+    fn accepts_sgr_params() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define_sgr("curly", "4:3").unwrap();
+        dahlia.define_sgr("x", "1;53").unwrap();
+
+        assert_eq!(dahlia.convert("&[curly]&xhi"), "\x1b[4:3m\x1b[1;53mhi");
+    }
+
+    #[test]
+    fn works_with_other_outputs() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        dahlia.define_sgr("over-line", "53").unwrap();
+
+        assert_eq!(dahlia.clean("&[error]a&[over-line]b"), "ab");
+        assert_eq!(
+            dahlia.wrap("&[error]ab cd", 2),
+            ["&[error]ab&R", "&c&lcd&R"]
+        );
+        assert_eq!(
+            dahlia.to_html("&[error]x"),
+            "<span style=\"color:#ff5555;font-weight:bold\">x</span>"
+        );
+    }
+
+    parametric_test! {
+        wraps,
+        [
+            (sgr, ("&[over-line]lined words", 6), vec!["&[over-line]lined&R", "&[over-line]words&R"]),
+            (
+                undone,
+                ("&[error]&n&[over-line]ab&rn cd", 2),
+                vec!["&[error]&n&[over-line]ab&rn&R", "&[over-line]&c&lcd&R"]
+            ),
+            (after_reset, ("&[over-line]ab&R cd", 2), vec!["&[over-line]ab&R", "cd"]),
+        ],
+        |(input, width)| {
+            let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+            dahlia.define("error", "&c&l").unwrap();
+            dahlia.define_sgr("over-line", "53").unwrap();
+            dahlia.wrap(input, width)
+        }
+    }
+
+    #[test]
+    fn wraps_nested() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        dahlia.define_sgr("over-line", "53").unwrap();
+        dahlia.define("fatal", "&[error]&n&[over-line]").unwrap();
+
+        assert_eq!(
+            dahlia.wrap("&[fatal]ab&rn cd", 2),
+            ["&[fatal]ab&rn&R", "&[fatal]&rn&c&lcd&R"]
+        );
+    }
+
+    #[test]
+    fn truncates() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("p", "&~1").unwrap();
+        dahlia.define_sgr("over-line", "53").unwrap();
+
+        assert_eq!(
+            dahlia.truncate("&[over-line]very long", 6, "&p…"),
+            "&[over-line]very &p…&R"
+        );
+    }
+
+    #[test]
+    fn validates_names() {
+        let mut dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        dahlia.define("error", "&c&l").unwrap();
+        let error = dahlia.validate("&[error]&[eror]").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid code `&[eror]` at byte 8: unknown name, use the name of a defined code"
+        );
+    }
+}
+
+mod themes {
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (high, Depth::High, "\x1b[38;2;0;196;140mx"),
+            (medium, Depth::Medium, "\x1b[38;5;42mx"),
+            (low, Depth::Low, "\x1b[92mx"),
+            (tty, Depth::Tty, "\x1b[32mx"),
+        ],
+        |depth| {
+            let mut theme = Theme::default();
+            theme.set_rgb('a', [0, 196, 140]);
+            Dahlia::new(Some(depth), false, '&').with_theme(theme).convert("&ax")
+        }
+    }
+
+    #[test]
+    fn default_is_unchanged() {
+        for depth in [Depth::High, Depth::Medium, Depth::Low, Depth::Tty] {
+            let themed = Dahlia::new(Some(depth), false, '&').with_theme(Theme::default());
+            let plain = Dahlia::new(Some(depth), false, '&');
+
+            assert_eq!(
+                themed.convert("&a&~4&#123456;x"),
+                plain.convert("&a&~4&#123456;x")
+            );
+        }
+    }
+
+    #[test]
+    fn downsamples_to_theme() {
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '&').with_theme(Theme::dracula());
+
+        // `&1` is #bd93f9 in Dracula, so it is the nearest palette color
+        assert_eq!(dahlia.convert("&#bd93f9;x"), "\x1b[34mx");
+    }
+
+    #[test]
+    fn presets() {
+        for theme in [
+            Theme::solarized_dark(),
+            Theme::dracula(),
+            Theme::gruvbox_dark(),
+        ] {
+            assert_ne!(theme, Theme::default());
+            assert_eq!(theme.get_ansi('a'), Theme::default().get_ansi('a'));
+        }
+        assert_eq!(Theme::dracula().get_rgb('c'), [255, 110, 110]);
+    }
+
+    #[test]
+    fn html() {
+        let mut theme = Theme::default();
+        theme.set_rgb('a', [0, 196, 140]);
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&').with_theme(theme);
+
+        assert_eq!(
+            dahlia.to_html("&ax"),
+            "<span style=\"color:#00c48c\">x</span>"
+        );
+        assert!(dahlia
+            .theme
+            .html_stylesheet()
+            .contains(".dahlia-a { color: #00c48c; }"));
+        assert!(html_stylesheet().contains(".dahlia-a { color: #55ff55; }"));
+    }
+
+    parametric_test! {
+        rejects_out_of_range_colors,
+        [
+            (ansi_high, ("ansi", 250), "invalid theme: 4-bit colors must be 30-37 or 90-97, got 250"),
+            (ansi_gap, ("ansi", 40), "invalid theme: 4-bit colors must be 30-37 or 90-97, got 40"),
+            (tty_bright, ("tty", 90), "invalid theme: 3-bit colors must be 30-37, got 90"),
+        ],
+        |(table, value)| {
+            let mut theme = Theme::default();
+            let error = match table {
+                "ansi" => theme.set_ansi('0', value),
+                _ => theme.set_tty('0', value),
+            };
+            assert_eq!(theme, Theme::default());
+            error.unwrap_err().to_string()
+        }
+    }
+
+    #[test]
+    fn background_of_bright_colors() {
+        let mut theme = Theme::default();
+        theme.set_ansi('0', 97).unwrap();
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '&').with_theme(theme);
+
+        assert_eq!(dahlia.convert("&~0x"), "\x1b[107mx");
+    }
+
+    #[test]
+    #[should_panic = "`g` is not a color code"]
+    fn invalid_code() {
+        Theme::default().set_rgb('g', [0, 0, 0]);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml() {
+        let theme = Theme::from_toml(
+            "[colors]\na = \"#00c48c\"\nlight_red = \"#f00\"\n\n[xterm]\nc = 160\n\n[tty]\nf = 30",
+        )
+        .unwrap();
+
+        assert_eq!(theme.get_rgb('a'), [0, 196, 140]);
+        assert_eq!(theme.get_xterm('a'), 42);
+        assert_eq!(theme.get_rgb('c'), [255, 0, 0]);
+        assert_eq!(theme.get_xterm('c'), 160);
+        assert_eq!(theme.get_tty('f'), 30);
+        assert_eq!(theme.get_rgb('0'), Theme::default().get_rgb('0'));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml_errors() {
+        for (toml, error) in [
+            (
+                "[colors]\nz = \"#fff\"",
+                "invalid theme: `z` is not a palette code or name",
+            ),
+            (
+                "[colors]\na = \"fff\"",
+                "invalid theme: `fff` is not a `#rgb` or `#rrggbb` color",
+            ),
+            (
+                "[ansi]\na = 50",
+                "invalid theme: `ansi.a`: 4-bit colors must be 30-37 or 90-97, got 50",
+            ),
+            (
+                "[tty]\na = 92",
+                "invalid theme: `tty.a`: 3-bit colors must be 30-37, got 92",
+            ),
+        ] {
+            assert_eq!(Theme::from_toml(toml).unwrap_err().to_string(), error);
+        }
+        assert!(matches!(
+            Theme::from_toml("[palette]"),
+            Err(ThemeError::Syntax(_))
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn from_json() {
+        let theme =
+            Theme::from_json(r##"{"colors": {"green": "#0a0"}, "ansi": {"2": 92}}"##).unwrap();
+
+        assert_eq!(theme.get_rgb('2'), [0, 170, 0]);
+        assert_eq!(theme.get_ansi('2'), 92);
+        assert!(matches!(Theme::from_json("{"), Err(ThemeError::Syntax(_))));
+    }
+
+    #[cfg(any(feature = "toml", feature = "json"))]
+    #[test]
+    fn load_unsupported() {
+        assert_eq!(
+            Theme::load("theme.yaml").unwrap_err().to_string(),
+            "invalid theme: unsupported theme file `theme.yaml`"
+        );
+        assert!(matches!(
+            Theme::load("missing.toml").or(Theme::load("missing.json")),
+            Err(ThemeError::Io(_))
+        ));
+    }
+}
+
+mod gradients {
+    use std::io::Write;
+
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (two_stops, "&[gradient:#f00:#00f]abc", "\x1b[38;2;255;0;0ma\x1b[38;2;128;0;128mb\x1b[38;2;0;0;255mc"),
+            (three_stops, "&[gradient:#ff0000:#00ff00:#0000ff]abc", "\x1b[38;2;255;0;0ma\x1b[38;2;0;255;0mb\x1b[38;2;0;0;255mc"),
+            (single_char, "&[gradient:#f00:#00f]a", "\x1b[38;2;255;0;0ma"),
+            (skips_spaces, "&[gradient:#f00:#00f]a b", "\x1b[38;2;255;0;0ma \x1b[38;2;0;0;255mb"),
+            (background_spaces, "&~[gradient:#000:#fff]a b", "\x1b[48;2;0;0;0ma\x1b[48;2;128;128;128m \x1b[48;2;255;255;255mb"),
+            (graphemes, "&[gradient:#f00:#00f]e\u{301}x", "\x1b[38;2;255;0;0me\u{301}\x1b[38;2;0;0;255mx"),
+            (escape, "&[gradient:#f00:#00f]a&_b", "\x1b[38;2;255;0;0ma\x1b[38;2;128;0;128m&\x1b[38;2;0;0;255mb"),
+            (ends_at_color, "&[gradient:#f00:#00f]ab&4c", "\x1b[38;2;255;0;0ma\x1b[38;2;0;0;255mb\x1b[38;2;170;0;0mc"),
+            (ends_at_reset, "&[gradient:#f00:#00f]ab&Rc", "\x1b[38;2;255;0;0ma\x1b[38;2;0;0;255mb\x1b[0mc"),
+            (keeps_formats, "&[gradient:#f00:#00f]a&lb", "\x1b[38;2;255;0;0ma\x1b[1m\x1b[38;2;0;0;255mb"),
+            (background_stays, "&~[gradient:#000:#fff]a&4b", "\x1b[48;2;0;0;0ma\x1b[38;2;170;0;0m\x1b[48;2;255;255;255mb"),
+            (same_colors, "&[gradient:#f00:#f00]abc", "\x1b[38;2;255;0;0mabc"),
+            (empty, "&[gradient:#f00:#00f]", ""),
+            (
+                rainbow,
+                "&[rainbow]abcdef",
+                "\x1b[38;2;255;0;0ma\x1b[38;2;255;255;0mb\x1b[38;2;0;255;0mc\x1b[38;2;0;255;255md\x1b[38;2;0;0;255me\x1b[38;2;255;0;255mf"
+            ),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&').convert(input)
+    }
+
+    #[test]
+    fn downsamples() {
+        let dahlia = Dahlia::new(Some(Depth::Low), false, '&');
+        assert_eq!(
+            dahlia.convert("&[gradient:#f00:#00f]ab"),
+            "\x1b[31ma\x1b[34mb"
+        );
+    }
+
+    #[test]
+    fn parses() {
+        let tokens: Vec<_> = Dahlia::new(Some(Depth::High), false, '&')
+            .parse("x&[gradient:#f00:#00f]ab")
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Spanned {
+                    token: Token::Text("x"),
+                    span: 0..1
+                },
+                Spanned {
+                    token: Token::Hex {
+                        rgb: [255, 0, 0],
+                        background: false
+                    },
+                    span: 22..22
+                },
+                Spanned {
+                    token: Token::Text("a"),
+                    span: 22..23
+                },
+                Spanned {
+                    token: Token::Hex {
+                        rgb: [0, 0, 255],
+                        background: false
+                    },
+                    span: 23..23
+                },
+                Spanned {
+                    token: Token::Text("b"),
+                    span: 23..24
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn other_outputs() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        let input = "&[gradient:#f00:#00f]ab&_";
+
+        assert_eq!(dahlia.clean(input), "ab&");
+        assert_eq!(dahlia.display_width(input), 3);
+        assert_eq!(
+            dahlia.to_html("&[gradient:#f00:#00f]ab"),
+            "<span style=\"color:#ff0000\">a</span><span style=\"color:#0000ff\">b</span>"
+        );
+        assert_eq!(
+            dahlia.to_minecraft_legacy("&[gradient:#f00:#00f]ab"),
+            "§x§f§f§0§0§0§0a§x§0§0§0§0§f§fb"
+        );
+    }
+
+    #[test]
+    fn dahlia1_has_no_gradients() {
+        let dahlia =
+            Dahlia::new(Some(Depth::High), false, '&').with_spec_version(SpecVersion::Dahlia1);
+        assert_eq!(dahlia.convert("&[rainbow]"), "&[rainbow]");
+    }
+
+    #[test]
+    fn ended_by_definitions() {
+        let mut dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        dahlia.define("p", "&4&l").unwrap();
+
+        assert_eq!(
+            dahlia.convert("&[gradient:#f00:#00f]ab&pc"),
+            "\x1b[38;2;255;0;0ma\x1b[38;2;0;0;255mb\x1b[38;2;170;0;0m\x1b[1mc"
+        );
+    }
+
+    #[test]
+    fn writer_holds_back_open_gradients() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        let mut writer = dahlia.writer(Vec::new());
+
+        // the colors depend on the text up to the end of the gradient
+        writer.write_all(b"x&[rainbow]ab").unwrap();
+        assert_eq!(writer.get_ref(), b"x");
+        assert_eq!(
+            writer.finish().unwrap(),
+            b"x\x1b[38;2;255;0;0ma\x1b[38;2;0;255;255mb"
+        );
+    }
+}
+
+mod links {
+    use super::*;
+
+    const LINKED: &str = "see &[link:https://x.io/?a=1&b=2]&4docs&R&[/link]!";
+
+    parametric_test! {
+        converts,
+        [
+            (link, "&[link:https://x.io]x&[/link]", "\x1b]8;;https://x.io\x1b\\x\x1b]8;;\x1b\\"),
+            (marker_in_url, LINKED, "see \x1b]8;;https://x.io/?a=1&b=2\x1b\\\x1b[31mdocs\x1b[0m\x1b]8;;\x1b\\!"),
+            (code_in_url, "&[link:a&4b&_]x", "\x1b]8;;a&4b&_\x1b\\x"),
+            (empty_url, "&[link:]x", "&[link:]x"),
+            (space_in_url, "&[link:a b]x", "&[link:a b]x"),
+        ],
+        |input| Dahlia::new(Some(Depth::Low), false, '&').convert(input)
+    }
+
+    parametric_test! {
+        parses,
+        [
+            (link, "&[link:https://x.io]", Token::Link("https://x.io")),
+            (end, "&[/link]", Token::LinkEnd),
+        ],
+        |input| Dahlia::new(Some(Depth::Low), false, '&').parse(input).next().unwrap().token
+    }
+
+    #[test]
+    fn to_markup() {
+        assert_eq!(Token::Link("u").to_markup('§'), "§[link:u]");
+        assert_eq!(Token::LinkEnd.to_markup('§'), "§[/link]");
+    }
+
+    #[test]
+    fn clean() {
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').clean(LINKED),
+            "see docs!"
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&')
+                .with_link_urls(true)
+                .clean(LINKED),
+            "see docs (https://x.io/?a=1&b=2)!"
+        );
+        assert_eq!(
+            Dahlia::new(None, false, '&')
+                .with_link_urls(true)
+                .convert(LINKED),
+            "see docs (https://x.io/?a=1&b=2)!"
+        );
+    }
+
+    #[test]
+    fn clean_ansi_strips_osc() {
+        for ansi in [
+            "\x1b]8;;https://a.b/c,d'e(f)*+!\x1b\\x\x1b]8;;\x1b\\",
+            "\x1b]8;id=1;https://a.b\x07x\x1b]8;;\x07",
+            "\x1b]0;window title\x07x",
+        ] {
+            assert_eq!(clean_ansi(ansi), "x", "{ansi:?}");
+        }
+    }
+
+    #[test]
+    fn ansi_round_trip() {
+        assert_eq!(
+            ansi_to_dahlia(
+                &Dahlia::new(Some(Depth::Low), false, '&').convert(LINKED),
+                '&'
+            ),
+            LINKED
+        );
+        assert_eq!(
+            ansi_to_dahlia("\x1b]8;id=1;https://a.b/c d]\x07x\x1b]8;;\x07", '&'),
+            "&[link:https://a.b/c%20d%5D]x&[/link]"
+        );
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').to_html(LINKED),
+            "see <a href=\"https://x.io/?a=1&amp;b=2\"><span style=\"color:#aa0000\">docs</span></a>!"
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').to_html("&4a&[link:u]b&[/link]c&[link:v]"),
+            "<span style=\"color:#aa0000\">a</span><a href=\"u\"><span style=\"color:#aa0000\">b</span></a>\
+             <span style=\"color:#aa0000\">c</span><a href=\"v\"></a>"
+        );
+    }
+
+    #[test]
+    fn minecraft() {
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').to_minecraft_json(LINKED),
+            r#"{"text":"","extra":[{"text":"see "},{"text":"docs","color":"dark_red","clickEvent":{"action":"open_url","value":"https://x.io/?a=1&b=2"}},{"text":"!"}]}"#
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').to_minecraft_legacy(LINKED),
+            "see §4docs§r!"
+        );
+    }
+
+    #[test]
+    fn wrap_reopens_links() {
+        let input = "&[link:u]&4aaa bbb&[/link] c";
+
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').wrap(input, 4),
+            [
+                "&[link:u]&4aaa&[/link]&R",
+                "&4&[link:u]bbb&[/link]&R",
+                "&4c&R"
+            ]
+        );
+        assert_eq!(
+            wrap_ansi(&Dahlia::new(Some(Depth::Low), false, '&').convert(input), 4),
+            [
+                "\x1b]8;;u\x1b\\\x1b[31maaa\x1b]8;;\x1b\\\x1b[0m",
+                "\x1b[31m\x1b]8;;u\x1b\\bbb\x1b]8;;\x1b\\\x1b[0m",
+                "\x1b[31mc\x1b[0m",
+            ]
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').truncate(LINKED, 7, "…"),
+            "see &[link:https://x.io/?a=1&b=2]&4do…&[/link]&R"
+        );
+    }
+}
+
+mod underlines {
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (double, "&sx&rs", "\x1b[4:2mx\x1b[24m"),
+            (curly, "&tx&rt", "\x1b[4:3mx\x1b[24m"),
+            (dotted, "&ux&ru", "\x1b[4:4mx\x1b[24m"),
+            (dashed, "&vx&rv", "\x1b[4:5mx\x1b[24m"),
+            (overline, "&wx&rw", "\x1b[53mx\x1b[55m"),
+            (underline_color, "&^cx&r^", "\x1b[58;2;255;85;85mx\x1b[59m"),
+            (underline_hex, "&^#123;x", "\x1b[58;2;17;34;51mx"),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&').convert(input)
+    }
+
+    #[test]
+    fn depths() {
+        let input = "&t&^#ff8000;x";
+
+        assert_eq!(
+            Dahlia::new(Some(Depth::Medium), false, '&').convert(input),
+            "\x1b[4:3m\x1b[58;5;208mx"
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Low), false, '&').convert(input),
+            "\x1b[4:3mx"
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Tty), false, '&').convert(input),
+            "\x1b[4mx"
+        );
+        assert_eq!(
+            Dahlia::new(Some(Depth::Medium), false, '&').convert("&^c"),
+            "\x1b[58;5;203m"
+        );
+    }
+
+    parametric_test! {
+        parses,
+        [
+            (double, "&s", Token::Format(Format::DoubleUnderline)),
+            (overline_reset, "&rw", Token::Reset(Reset::Format(Format::Overline))),
+            (underline_color, "&^c", Token::UnderlineColor { code: 'c' }),
+            (underline_hex, "&^#fff;", Token::UnderlineHex { rgb: [255; 3] }),
+            (underline_color_reset, "&r^", Token::Reset(Reset::UnderlineColor)),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&').parse(input).next().unwrap().token
+    }
+
+    #[test]
+    fn to_markup() {
+        assert_eq!(Token::UnderlineColor { code: '4' }.to_markup('&'), "&^4");
+        assert_eq!(
+            Token::UnderlineHex { rgb: [1, 2, 3] }.to_markup('&'),
+            "&^#010203;"
+        );
+        assert_eq!(Token::Reset(Reset::UnderlineColor).to_markup('&'), "&r^");
+    }
+
+    #[test]
+    fn named_underline_color() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&').with_named_colors(true);
+        assert_eq!(dahlia.convert("&^[red]x"), "\x1b[58;2;170;0;0mx");
+    }
+
+    #[test]
+    fn styles_replace_each_other() {
+        // the curly underline replaces the single one, so resetting it ends both
+        assert_eq!(
+            Dahlia::new(Some(Depth::High), false, '&').wrap("&n&t&^caaa&rt bbb", 4),
+            ["&n&t&^caaa&rt&R", "&^cbbb&R"]
+        );
+    }
+
+    parametric_test! {
+        from_ansi,
+        [
+            (styles, "\x1b[4:2m\x1b[4:3m\x1b[4:4m\x1b[4:5m\x1b[4:1m", "&s&t&u&v&n"),
+            (double, "\x1b[21m", "&s"),
+            (underline_reset, "\x1b[4:0m", "&rn"),
+            (overline, "\x1b[53m\x1b[55m", "&w&rw"),
+            (underline_rgb, "\x1b[58;2;1;2;3m", "&^#010203;"),
+            (underline_colon, "\x1b[58:2::1:2:3m", "&^#010203;"),
+            (underline_palette, "\x1b[58;5;203;59m", "&^c&r^"),
+        ],
+        |input| ansi_to_dahlia(input, '&')
+    }
+
+    #[test]
+    fn html() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+
+        assert_eq!(
+            dahlia.to_html("&t&^cx&m&wy"),
+            "<span style=\"text-decoration-color:#ff5555;text-decoration-style:wavy;\
+             text-decoration-line:underline\">x</span>\
+             <span style=\"text-decoration-color:#ff5555;text-decoration-style:wavy;\
+             text-decoration-line:underline overline line-through\">y</span>"
+        );
+        assert_eq!(
+            dahlia.to_html_classes("&s&^cx&^#123456;y"),
+            "<span class=\"dahlia-ul-c dahlia-double-underline dahlia-underline\">x</span>\
+             <span class=\"dahlia-double-underline dahlia-underline\" \
+             style=\"text-decoration-color:#123456\">y</span>"
+        );
+
+        let css = html_stylesheet();
+        for rule in [
+            ".dahlia-ul-c { text-decoration-color: #ff5555; }",
+            ".dahlia-curly-underline { text-decoration-style: wavy; }",
+            ".dahlia-underline.dahlia-overline { text-decoration-line: underline overline; }",
+        ] {
+            assert!(css.contains(rule), "{rule}");
+        }
+    }
+
+    #[test]
+    fn minecraft() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+
+        assert_eq!(
+            dahlia.to_minecraft_json("&t&^cx&w"),
+            r#"{"text":"x","underlined":true}"#
+        );
+        assert_eq!(dahlia.to_minecraft_legacy("&ux&ruy"), "§nx§ry");
+    }
+}
+
+mod xterm {
+    use super::*;
+
+    parametric_test! {
+        converts,
+        [
+            (high, (Depth::High, "&@208;a&~@57;b&^@57;c"), "\x1b[38;5;208ma\x1b[48;5;57mb\x1b[58;5;57mc"),
+            (medium, (Depth::Medium, "&@208;a&~@57;b&^@57;c"), "\x1b[38;5;208ma\x1b[48;5;57mb\x1b[58;5;57mc"),
+            (low, (Depth::Low, "&@208;a&~@9;b&^@57;c"), "\x1b[33ma\x1b[101mbc"),
+            (tty, (Depth::Tty, "&@208;a&~@9;b&^@57;c"), "\x1b[33ma\x1b[41mbc"),
+            (system, (Depth::Low, "&@4;a&@12;b"), "\x1b[34ma\x1b[94mb"),
+            (out_of_range, (Depth::High, "&@256;"), "&@256;"),
+            (leading_zero, (Depth::High, "&@07;"), "&@07;"),
+        ],
+        |(depth, input)| Dahlia::new(Some(depth), false, '&').convert(input)
+    }
+
+    parametric_test! {
+        parses,
+        [
+            (foreground, "&@0;", Token::Xterm { index: 0, background: false }),
+            (background, "&~@255;", Token::Xterm { index: 255, background: true }),
+            (underline, "&^@99;", Token::UnderlineXterm { index: 99 }),
+        ],
+        |input| Dahlia::new(Some(Depth::High), false, '&').parse(input).next().unwrap().token
+    }
+
+    #[test]
+    fn to_markup() {
+        let token = Token::Xterm {
+            index: 208,
+            background: true,
+        };
+        assert_eq!(token.to_markup('&'), "&~@208;");
+        assert_eq!(Token::UnderlineXterm { index: 1 }.to_markup('&'), "&^@1;");
+    }
+
+    #[test]
+    fn ansi_round_trip() {
+        let ansi = "\x1b[38;5;208m\x1b[48;5;57m\x1b[58;5;57mx";
+        assert_eq!(ansi_to_dahlia(ansi, '&'), "&@208;&~@57;&^@57;x");
+        assert_eq!(
+            Dahlia::new(Some(Depth::Medium), false, '&').convert("&@208;&~@57;&^@57;x"),
+            ansi
+        );
+    }
+
+    #[test]
+    fn html_and_minecraft() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+
+        assert_eq!(
+            dahlia.to_html_classes("&@208;a&~@21;b"),
+            "<span style=\"color:#ff8700\">a</span>\
+             <span style=\"color:#ff8700;background-color:#0000ff\">b</span>"
+        );
+        assert_eq!(
+            dahlia.to_minecraft_json("&@208;a"),
+            r##"{"text":"a","color":"#ff8700"}"##
+        );
+        assert_eq!(dahlia.to_minecraft_legacy("&@208;a"), "§x§f§f§8§7§0§0a");
+    }
+
+    #[test]
+    fn ends_gradients() {
+        assert_eq!(
+            Dahlia::new(Some(Depth::High), false, '&').convert("&[rainbow]xy&@1;z"),
+            "\x1b[38;2;255;0;0mx\x1b[38;2;0;255;255my\x1b[38;5;1mz"
+        );
+    }
+}
+
+#[cfg(feature = "log")]
+mod logger {
+    use log::{Level, LevelFilter, Log, Record};
+
+    use super::*;
+
+    fn format(logger: &DahliaLogger, level: Level, message: &str) -> String {
+        logger.format(
+            &Record::builder()
+                .level(level)
+                .args(format_args!("{message}"))
+                .build(),
+        )
+    }
+
+    parametric_test! {
+        default_templates,
+        [
+            (error, Level::Error, "\x1b[91m\x1b[1m[ERROR]\x1b[0m x\x1b[0m"),
+            (warn, Level::Warn, "\x1b[93m\x1b[1m[WARN]\x1b[0m x\x1b[0m"),
+            (info, Level::Info, "\x1b[92m[INFO]\x1b[0m x\x1b[0m"),
+            (debug, Level::Debug, "\x1b[96m[DEBUG]\x1b[0m x\x1b[0m"),
+            (trace, Level::Trace, "\x1b[90m[TRACE]\x1b[0m x\x1b[0m"),
+        ],
+        |level| format(&DahliaLogger::new().with_depth(Depth::Low), level, "x")
+    }
+
+    #[test]
+    fn converts_messages() {
+        let logger = DahliaLogger::new()
+            .with_depth(Depth::Low)
+            .with_template(Level::Info, "&9info:&R");
+
+        assert_eq!(
+            format(&logger, Level::Info, "listening on &lport 80"),
+            "\x1b[94minfo:\x1b[0m listening on \x1b[1mport 80\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn custom_dahlia() {
+        let dahlia = Dahlia::new(None, false, '§').with_named_colors(true);
+        let logger = DahliaLogger::new()
+            .with_dahlia(dahlia)
+            .with_depth(Depth::High)
+            .with_template(Level::Warn, "§[orange]!");
+
+        assert_eq!(
+            format(&logger, Level::Warn, "a & b"),
+            "\x1b[38;2;255;170;0m! a & b"
+        );
+    }
+
+    #[test]
+    fn levels() {
+        let logger = DahliaLogger::new().with_level(LevelFilter::Warn);
+        let metadata = |level| log::Metadata::builder().level(level).build();
+
+        assert!(logger.enabled(&metadata(Level::Error)));
+        assert!(logger.enabled(&metadata(Level::Warn)));
+        assert!(!logger.enabled(&metadata(Level::Info)));
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod subscriber {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::Level;
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(format: DahliaFormat, ansi: bool, emit: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let layer = format
+            .layer()
+            .with_writer(move || writer.clone())
+            .with_ansi(ansi);
+
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), emit);
+
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    parametric_test! {
+        default_templates,
+        [
+            (error, Level::ERROR, "\x1b[91m\x1b[1mERROR\x1b[0m \x1b[90mapp\x1b[0m: x\x1b[0m\n"),
+            (warn, Level::WARN, "\x1b[93m\x1b[1mWARN\x1b[0m \x1b[90mapp\x1b[0m: x\x1b[0m\n"),
+            (info, Level::INFO, "\x1b[92mINFO\x1b[0m \x1b[90mapp\x1b[0m: x\x1b[0m\n"),
+            (debug, Level::DEBUG, "\x1b[96mDEBUG\x1b[0m \x1b[90mapp\x1b[0m: x\x1b[0m\n"),
+            (trace, Level::TRACE, "\x1b[90mTRACE\x1b[0m \x1b[90mapp\x1b[0m: x\x1b[0m\n"),
+        ],
+        |level| {
+            let format = DahliaFormat::new().with_depth(Depth::Low);
+            capture(format, true, || match level {
+                Level::ERROR => tracing::error!(target: "app", "x"),
+                Level::WARN => tracing::warn!(target: "app", "x"),
+                Level::INFO => tracing::info!(target: "app", "x"),
+                Level::DEBUG => tracing::debug!(target: "app", "x"),
+                Level::TRACE => tracing::trace!(target: "app", "x"),
+            })
+        }
+    }
+
+    #[test]
+    fn converts_messages() {
+        let format = DahliaFormat::new()
+            .with_depth(Depth::Low)
+            .with_template(Level::INFO, "&9info:&R")
+            .with_target_style("")
+            .with_field_style("&e");
+
+        assert_eq!(
+            capture(format, true, || {
+                tracing::info!(target: "app", user = "&cbob", "logged &lin");
+            }),
+            "\x1b[94minfo:\x1b[0m app\x1b[0m: logged \x1b[1min \x1b[93muser\x1b[0m=\"&cbob\"\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let format = DahliaFormat::new()
+            .with_template(Level::INFO, "I")
+            .with_span_style("&a");
+
+        assert_eq!(
+            capture(format, false, || {
+                let outer = tracing::info_span!("request", id = 7);
+                let _outer = outer.enter();
+                let inner = tracing::info_span!("&db");
+                let _inner = inner.enter();
+                tracing::info!(target: "app", done = true);
+            }),
+            "I request{id=7}:&db: app: done=true\n"
+        );
+    }
+
+    #[test]
+    fn escapes_field_names() {
+        let format = DahliaFormat::new()
+            .with_depth(Depth::Low)
+            .with_template(Level::INFO, "I")
+            .with_target_style("")
+            .with_field_style("&e");
+
+        assert_eq!(
+            capture(
+                format,
+                true,
+                || tracing::info!(target: "app", done = true, "a&lb" = 1)
+            ),
+            "I app\x1b[0m: \x1b[93mdone\x1b[0m=true \x1b[93ma&lb\x1b[0m=1\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn custom_dahlia() {
+        let dahlia = Dahlia::new(None, false, '§').with_named_colors(true);
+        let format = DahliaFormat::new()
+            .with_dahlia(dahlia)
+            .with_depth(Depth::High)
+            .with_template(Level::WARN, "§[orange]!")
+            .with_target_style("§l");
+
+        assert_eq!(
+            capture(format, true, || tracing::warn!(target: "a§b", "&c")),
+            "\x1b[38;2;255;170;0m! \x1b[1ma§b\x1b[0m: &c\n"
+        );
+    }
+}
+
+mod preview {
+    use super::*;
+
+    parametric_test! {
+        palette_at_every_depth,
+        [
+            (tty, "tty       \x1b[30m0", true),
+            (low, "low       \x1b[30m0", true),
+            (medium, "medium    \x1b[38;5;0m0", true),
+            (high, "high      \x1b[38;2;0;0;0m0", true),
+        ],
+        |row| Dahlia::new(None, true, '&').preview().contains(row)
+    }
+
+    #[test]
+    fn formats_at_own_depth() {
+        let preview = Dahlia::default().with_depth(Depth::Tty).preview();
+
+        assert!(preview.contains("Formats and resets at tty:"));
+        assert!(preview.contains("&l \x1b[1mbold\x1b[0m"));
+        // the extended underline styles fall back to a plain underline
+        assert!(preview.contains("&t \x1b[4mcurly-underline\x1b[0m"));
+        assert!(preview.contains("&rb \x1b[31m\x1b[40mon\x1b[49moff\x1b[0m"));
+    }
+
+    #[test]
+    fn formats_without_colors() {
+        let preview = Dahlia::new(None, true, '§').preview();
+
+        assert!(preview.contains("Formats and resets at none (no colors):"));
+        assert!(preview.contains("&l bold  &m strikethrough"));
+        assert!(preview.contains("&rl onoff"));
+    }
+}
+
+mod scan {
+    use super::*;
+
+    const FRAGMENTS: [&str; 58] = [
+        "&0",
+        "&f",
+        "&g",
+        "&~a",
+        "&^c",
+        "&~^c",
+        "&#abc;",
+        "&#abcdef;",
+        "&#abcd;",
+        "&#ABC;",
+        "&#abc",
+        "&#12345;",
+        "&@0;",
+        "&@05;",
+        "&@99;",
+        "&@255;",
+        "&@256;",
+        "&@1234;",
+        "&@;",
+        "&~@7;",
+        "&^@200;",
+        "&^#fff;",
+        "&~#000000;",
+        "&l",
+        "&s",
+        "&w",
+        "&R",
+        "&r",
+        "&rb",
+        "&rc",
+        "&rf",
+        "&r^",
+        "&rw",
+        "&rx",
+        "&x",
+        "&y",
+        "&_",
+        "&",
+        "&&",
+        "&[rainbow]",
+        "&~[gradient:#f00:#00f]",
+        "&[gradient:#f00]",
+        "&[link:https://x.io/?a&b]",
+        "&[link:]",
+        "&[/link]",
+        "&[red]",
+        "&^[light_red]",
+        "&~[tomato]",
+        "&[error]",
+        "&[blink-fast]",
+        "&[nope]",
+        "&[",
+        "&~",
+        "&^",
+        "text",
+        "§",
+        "日本",
+        " ",
+    ];
+
+    const FRAGMENTS_DAHLIA1: [&str; 16] = [
+        "&[#ffaff3]",
+        "&~[#FFAFF3]",
+        "&[#fff]",
+        "&4",
+        "&g",
+        "&~g",
+        "&k",
+        "&p",
+        "&r",
+        "&R",
+        "&x",
+        "&[error]",
+        "&_",
+        "&",
+        "&[",
+        "text",
+    ];
+
+    /// Instances with every kind of code, finding them with the scanner and
+    /// with the regex.
+    fn dahlias(marker: char, spec: SpecVersion) -> [Dahlia; 2] {
+        [false, true].map(|regex| {
+            let mut dahlia = Dahlia::new(None, false, marker)
+                .with_depth(Depth::High)
+                .with_spec_version(spec)
+                .with_named_colors(true);
+            if regex {
+                dahlia = dahlia.with_regex();
+            }
+            dahlia.define("x", &format!("{marker}l")).unwrap();
+            dahlia
+                .define("error", &format!("{marker}c{marker}n"))
+                .unwrap();
+            dahlia.define_sgr("blink-fast", "6").unwrap();
+            dahlia
+        })
+    }
+
+    #[test]
+    fn matches_regex() {
+        // only the codes are found differently, so a single depth is enough
+        for marker in ['&', '§'] {
+            let [scanner, regex] = dahlias(marker, SpecVersion::Dahlia2);
+
+            for a in FRAGMENTS {
+                for b in FRAGMENTS {
+                    let str = format!("{a}{b}x").replace('&', &marker.to_string());
+
+                    assert_eq!(scanner.convert(&str), regex.convert(&str), "{str:?}");
+                    assert_eq!(scanner.clean(&str), regex.clean(&str), "{str:?}");
+                    assert!(scanner.parse(&str).eq(regex.parse(&str)), "{str:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_regex_dahlia1() {
+        let [scanner, regex] = dahlias('&', SpecVersion::Dahlia1);
+
+        for a in FRAGMENTS_DAHLIA1 {
+            for b in FRAGMENTS_DAHLIA1 {
+                let str = format!("{a}{b}x");
+
+                assert_eq!(scanner.convert(&str), regex.convert(&str), "{str:?}");
+                assert!(scanner.parse(&str).eq(regex.parse(&str)), "{str:?}");
+            }
+        }
+    }
+
+    parametric_test! {
+        finds_codes,
+        [
+            (gradient, "a&[rainbow]b", vec![(Code::Gradient { stops: None, background: false }, 1..11)]),
+            (
+                gradient_stops,
+                "&~[gradient:#f00:#00f]",
+                vec![(Code::Gradient { stops: Some(":#f00:#00f"), background: true }, 0..22)]
+            ),
+            (
+                defined,
+                "&x&[error]&[nope]&[blink-fast]",
+                vec![(Code::Defined("x"), 0..2), (Code::Defined("error"), 2..10), (Code::Defined("blink-fast"), 17..30)]
+            ),
+            (
+                named,
+                "&^[red]&[tomato]",
+                vec![
+                    (Code::Token(Token::UnderlineColor { code: '4' }), 0..7),
+                    (Code::Token(Token::Hex { rgb: [255, 99, 71], background: false }), 7..16),
+                ]
+            ),
+            (
+                marker_in_url,
+                "&[link:a&b]&4",
+                vec![
+                    (Code::Token(Token::Link("a&b")), 0..11),
+                    (Code::Token(Token::Color { code: '4', background: false }), 11..13),
+                ]
+            ),
+            (unknown_bracket, "&[x&_", vec![(Code::Token(Token::Escape), 3..5)]),
+        ],
+        |input| dahlias('&', SpecVersion::Dahlia2)[0].scanner().codes(input).collect::<Vec<_>>()
+    }
+}
+
+mod wrap {
+    use super::*;
+
+    parametric_test! {
+        wraps_markup,
+        [
+            (empty, ("", 10), vec![""]),
+            (fits, ("hello world", 11), vec!["hello world"]),
+            (plain, ("hello big world", 9), vec!["hello big", "world"]),
+            (long_word, ("abcdefgh", 3), vec!["abc", "def", "gh"]),
+            (
+                restores_style,
+                ("&4&~f&nred on white&rn text", 6),
+                vec!["&4&~f&nred on&R", "&4&~f&nwhite&R", "&4&~ftext&R"]
+            ),
+            (
+                hex,
+                ("&#123456;aa bb", 2),
+                vec!["&#123456;aa&R", "&#123456;bb&R"]
+            ),
+            (explicit_reset, ("&4ab&R cd", 2), vec!["&4ab&R", "cd"]),
+            (
+                code_at_break,
+                ("&4ab &2cd", 2),
+                vec!["&4ab&R", "&2cd&R"]
+            ),
+            (
+                newlines,
+                ("&lone\n\ntwo", 10),
+                vec!["&lone&R", "", "&ltwo&R"]
+            ),
+            (trailing_spaces, ("ab   \ncd", 10), vec!["ab", "cd"]),
+            (escapes, ("&_4 &z", 2), vec!["&_4", "&_z"]),
+            (wide, ("日本語", 4), vec!["日本", "語"]),
+            (indent, ("  a b", 3), vec!["  a", "b"]),
+        ],
+        |(input, width)| Dahlia::default().wrap(input, width)
+    }
+
+    parametric_test! {
+        wraps_ansi,
+        [
+            (plain, ("hello world", 5), vec!["hello", "world"]),
+            (
+                restores_style,
+                ("\x1b[1m\x1b[31mbold red\x1b[0m x", 4),
+                vec!["\x1b[1m\x1b[31mbold\x1b[0m", "\x1b[1m\x1b[31mred\x1b[0m", "x"]
+            ),
+            (
+                reset_in_sequence,
+                ("\x1b[31ma \x1b[0;32mb", 1),
+                vec!["\x1b[31ma\x1b[0m", "\x1b[0;32mb\x1b[0m"]
+            ),
+        ],
+        |(input, width)| wrap_ansi(input, width)
+    }
+
+    #[test]
+    fn lines_fit_width() {
+        let dahlia = Dahlia::new(Some(Depth::High), false, '&');
+        let input = "&e&lLorem ipsum &~4dolor sit&rb amet, consectetur adipiscing elit";
+
+        for width in 1..20 {
+            for line in dahlia.wrap(input, width) {
+                assert!(
+                    dahlia.display_width(&line) <= width,
+                    "{line:?} wider than {width}"
+                );
+            }
+        }
+    }
+}
+
+mod truncate {
+    use super::*;
+
+    parametric_test! {
+        truncates_markup,
+        [
+            (fits, ("&4hello", 5, "…"), "&4hello"),
+            (plain, ("hello world", 8, "..."), "hello..."),
+            (keeps_style, ("&~e&lhello&R world", 8, "…"), "&~e&lhello&R w…"),
+            (styled_ellipsis, ("&4hello world", 6, "&R&8…"), "&4hello&R&8…&R"),
+            (unstyled_ellipsis, ("&4hello world", 6, "&R…"), "&4hello&R…"),
+            (drops_later_codes, ("ab&4cd", 3, "…"), "ab…"),
+            (wide, ("日本語", 5, "…"), "日本…"),
+            (wide_cut, ("日本語", 4, "…"), "日…"),
+            (escape, ("&_4&_4&_4", 2, "…"), "&_…"),
+            (empty_ellipsis, ("&lbold text", 4, ""), "&lbold&R"),
+            (long_ellipsis, ("hello", 2, "..."), ".."),
+        ],
+        |(input, width, ellipsis)| Dahlia::default().truncate(input, width, ellipsis)
+    }
+
+    parametric_test! {
+        truncates_ansi,
+        [
+            (fits, ("\x1b[31mhi\x1b[0m", 2, "…"), "\x1b[31mhi\x1b[0m"),
+            (
+                keeps_style,
+                ("\x1b[1mbold\x1b[0m text", 6, "…"),
+                "\x1b[1mbold\x1b[0m …"
+            ),
+            (
+                closes_style,
+                ("\x1b[48;5;1mbackground", 4, "…"),
+                "\x1b[48;5;1mbac…\x1b[0m"
+            ),
+        ],
+        |(input, width, ellipsis)| truncate_ansi(input, width, ellipsis)
+    }
+}
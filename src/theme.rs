@@ -0,0 +1,358 @@
+#[cfg(any(feature = "toml", feature = "json"))]
+use std::{collections::BTreeMap, path::Path};
+
+#[cfg(any(feature = "toml", feature = "json"))]
+use crate::consts::PALETTE_NAMES;
+use crate::{
+    color::{nearest_xterm, palette_rgb, COLOR_CODES},
+    consts::{COLORS_3BIT, COLORS_4BIT, COLORS_8BIT},
+    html,
+    scan::color_index,
+    ColorDistance, ThemeError,
+};
+
+/// The colors the 16 palette codes (`0` to `f`) stand for at every color depth.
+///
+/// The default theme has the Minecraft colors listed in the README. The
+/// colors are read and set per palette code, and the 4-bit and 3-bit colors
+/// can only be set to the SGR parameters of foreground colors.
+///
+/// ### Example
+/// ```rust
+/// # use dahlia::{Dahlia, Depth, Theme};
+/// let mut theme = Theme::default();
+/// theme.set_rgb('a', [0, 196, 140]);
+///
+/// let dahlia = Dahlia::default().with_depth(Depth::High).with_theme(theme);
+/// assert_eq!(dahlia.convert("&aok"), "\x1b[38;2;0;196;140mok\x1b[0m");
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Theme {
+    // 24-bit colors, used at `Depth::High` and for HTML
+    pub(crate) rgb: [[u8; 3]; 16],
+    // xterm-256 color indices, used at `Depth::Medium`
+    pub(crate) xterm: [u8; 16],
+    // 4-bit SGR foreground parameters (30-37 and 90-97), used at `Depth::Low`
+    pub(crate) ansi: [u8; 16],
+    // 3-bit SGR foreground parameters (30-37), used at `Depth::Tty`
+    pub(crate) tty: [u8; 16],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let table = |colors: fn(&str) -> Option<&'static str>| {
+            COLOR_CODES.map(|code| {
+                colors(code.encode_utf8(&mut [0; 4]))
+                    .and_then(|value| value.parse().ok())
+                    .expect("color tables should contain valid numbers for every code")
+            })
+        };
+
+        Self {
+            rgb: COLOR_CODES
+                .map(|code| palette_rgb(code).expect("every color code should have an RGB value")),
+            xterm: table(COLORS_8BIT),
+            ansi: table(COLORS_4BIT),
+            tty: table(COLORS_3BIT),
+        }
+    }
+}
+
+impl Theme {
+    /// Creates a theme from 24-bit colors, in palette order.
+    ///
+    /// The xterm-256 indices are the closest ones to the colors, the 4-bit
+    /// and 3-bit colors are the default ones, as those are already themed by
+    /// the terminal.
+    pub fn from_rgb(rgb: [[u8; 3]; 16]) -> Self {
+        Self {
+            rgb,
+            xterm: rgb.map(|rgb| nearest_xterm(rgb, ColorDistance::Euclidean)),
+            ..Self::default()
+        }
+    }
+
+    /// The [Solarized](https://ethanschoonover.com/solarized/) dark palette.
+    pub fn solarized_dark() -> Self {
+        Self::from_rgb(hex_palette([
+            0x073642, 0x268bd2, 0x859900, 0x2aa198, 0xdc322f, 0xd33682, 0xb58900, 0xeee8d5,
+            0x002b36, 0x839496, 0x586e75, 0x93a1a1, 0xcb4b16, 0x6c71c4, 0x657b83, 0xfdf6e3,
+        ]))
+    }
+
+    /// The [Dracula](https://draculatheme.com/) palette.
+    pub fn dracula() -> Self {
+        Self::from_rgb(hex_palette([
+            0x21222c, 0xbd93f9, 0x50fa7b, 0x8be9fd, 0xff5555, 0xff79c6, 0xf1fa8c, 0xf8f8f2,
+            0x6272a4, 0xd6acff, 0x69ff94, 0xa4ffff, 0xff6e6e, 0xff92df, 0xffffa5, 0xffffff,
+        ]))
+    }
+
+    /// The [Gruvbox](https://github.com/morhetz/gruvbox) dark palette.
+    pub fn gruvbox_dark() -> Self {
+        Self::from_rgb(hex_palette([
+            0x282828, 0x458588, 0x98971a, 0x689d6a, 0xcc241d, 0xb16286, 0xd79921, 0xa89984,
+            0x928374, 0x83a598, 0xb8bb26, 0x8ec07c, 0xfb4934, 0xd3869b, 0xfabd2f, 0xebdbb2,
+        ]))
+    }
+
+    /// Returns the CSS rules for the classes emitted by
+    /// [`Dahlia::to_html_classes`](crate::Dahlia::to_html_classes), with the
+    /// colors of this theme.
+    pub fn html_stylesheet(&self) -> String {
+        html::stylesheet(self)
+    }
+
+    /// Returns the 24-bit color of a palette code.
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn get_rgb(&self, code: char) -> [u8; 3] {
+        self.rgb[color_index(code)]
+    }
+
+    /// Sets the 24-bit color of a palette code, and its xterm-256 index to
+    /// the closest one.
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn set_rgb(&mut self, code: char, rgb: [u8; 3]) {
+        let index = color_index(code);
+        self.rgb[index] = rgb;
+        self.xterm[index] = nearest_xterm(rgb, ColorDistance::Euclidean);
+    }
+
+    /// Returns the xterm-256 index of a palette code, used at
+    /// [`Depth::Medium`](crate::Depth::Medium).
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn get_xterm(&self, code: char) -> u8 {
+        self.xterm[color_index(code)]
+    }
+
+    /// Sets the xterm-256 index of a palette code, which [`set_rgb`](Self::set_rgb)
+    /// sets to the closest one.
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn set_xterm(&mut self, code: char, xterm: u8) {
+        self.xterm[color_index(code)] = xterm;
+    }
+
+    /// Returns the 4-bit SGR foreground parameter of a palette code, used at
+    /// [`Depth::Low`](crate::Depth::Low).
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn get_ansi(&self, code: char) -> u8 {
+        self.ansi[color_index(code)]
+    }
+
+    /// Sets the 4-bit SGR foreground parameter of a palette code, which has
+    /// to be one of 30-37 and 90-97.
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use dahlia::Theme;
+    /// let mut theme = Theme::default();
+    /// theme.set_ansi('a', 32).unwrap();
+    /// assert!(theme.set_ansi('a', 250).is_err());
+    /// assert_eq!(theme.get_ansi('a'), 32);
+    /// ```
+    pub fn set_ansi(&mut self, code: char, ansi: u8) -> Result<(), ThemeError> {
+        if !matches!(ansi, 30..=37 | 90..=97) {
+            return Err(ThemeError::Invalid(format!(
+                "4-bit colors must be 30-37 or 90-97, got {ansi}"
+            )));
+        }
+        self.ansi[color_index(code)] = ansi;
+        Ok(())
+    }
+
+    /// Returns the 3-bit SGR foreground parameter of a palette code, used at
+    /// [`Depth::Tty`](crate::Depth::Tty).
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn get_tty(&self, code: char) -> u8 {
+        self.tty[color_index(code)]
+    }
+
+    /// Sets the 3-bit SGR foreground parameter of a palette code, which has
+    /// to be one of 30-37.
+    ///
+    /// # Panics
+    ///
+    /// If `code` isn't one of `0-9a-f`.
+    pub fn set_tty(&mut self, code: char, tty: u8) -> Result<(), ThemeError> {
+        if !(30..=37).contains(&tty) {
+            return Err(ThemeError::Invalid(format!(
+                "3-bit colors must be 30-37, got {tty}"
+            )));
+        }
+        self.tty[color_index(code)] = tty;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "toml", feature = "json"))]
+impl Theme {
+    /// Parses a theme from TOML, see [`Theme::load`] for the format.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, ThemeError> {
+        let file: ThemeFile =
+            toml::from_str(toml).map_err(|error| ThemeError::Syntax(error.message().to_owned()))?;
+        file.into_theme()
+    }
+
+    /// Parses a theme from JSON, see [`Theme::load`] for the format.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, ThemeError> {
+        let file: ThemeFile =
+            serde_json::from_str(json).map_err(|error| ThemeError::Syntax(error.to_string()))?;
+        file.into_theme()
+    }
+
+    /// Loads a theme from a `.toml` (with the `toml` feature) or `.json`
+    /// (with the `json` feature) file.
+    ///
+    /// Every table is optional and maps palette codes (`a`) or names
+    /// (`light_green`) to values, colors that aren't listed keep their
+    /// default values:
+    ///
+    /// ```toml
+    /// [colors]      # 24-bit colors, `#rgb` or `#rrggbb`
+    /// a = "#00c48c"
+    /// light_red = "#ff4f5e"
+    ///
+    /// [xterm]       # xterm-256 indices, the closest ones to `colors` by default
+    /// a = 36
+    ///
+    /// [ansi]        # 4-bit SGR parameters, 30-37 or 90-97
+    /// [tty]         # 3-bit SGR parameters, 30-37
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ThemeError> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&std::fs::read_to_string(path)?),
+            #[cfg(feature = "json")]
+            Some("json") => Self::from_json(&std::fs::read_to_string(path)?),
+            _ => Err(ThemeError::Invalid(format!(
+                "unsupported theme file `{}`",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// The structure of a theme file, see [`Theme::load`].
+#[cfg(any(feature = "toml", feature = "json"))]
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    colors: BTreeMap<String, String>,
+    xterm: BTreeMap<String, u8>,
+    ansi: BTreeMap<String, u8>,
+    tty: BTreeMap<String, u8>,
+}
+
+#[cfg(any(feature = "toml", feature = "json"))]
+impl ThemeFile {
+    fn into_theme(self) -> Result<Theme, ThemeError> {
+        let mut theme = Theme::default();
+
+        for (key, value) in &self.colors {
+            theme.set_rgb(code(key)?, parse_hex(value)?);
+        }
+        // explicit indices win over the ones derived from `colors`
+        for (key, &value) in &self.xterm {
+            theme.set_xterm(code(key)?, value);
+        }
+        for (key, &value) in &self.ansi {
+            theme
+                .set_ansi(code(key)?, value)
+                .map_err(|error| in_key(error, "ansi", key))?;
+        }
+        for (key, &value) in &self.tty {
+            theme
+                .set_tty(code(key)?, value)
+                .map_err(|error| in_key(error, "tty", key))?;
+        }
+
+        Ok(theme)
+    }
+}
+
+/// Adds the key of an invalid value to the error message.
+#[cfg(any(feature = "toml", feature = "json"))]
+fn in_key(error: ThemeError, table: &str, key: &str) -> ThemeError {
+    match error {
+        ThemeError::Invalid(message) => ThemeError::Invalid(format!("`{table}.{key}`: {message}")),
+        error => error,
+    }
+}
+
+/// Resolves a palette code or name used as a key in a theme file.
+#[cfg(any(feature = "toml", feature = "json"))]
+fn code(key: &str) -> Result<char, ThemeError> {
+    let mut chars = key.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        if COLOR_CODES.contains(&ch) {
+            return Ok(ch);
+        }
+    }
+
+    PALETTE_NAMES
+        .iter()
+        .position(|&name| name == key)
+        .map(|index| COLOR_CODES[index])
+        .ok_or_else(|| ThemeError::Invalid(format!("`{key}` is not a palette code or name")))
+}
+
+/// Parses a `#rgb` or `#rrggbb` color.
+#[cfg(any(feature = "toml", feature = "json"))]
+fn parse_hex(value: &str) -> Result<[u8; 3], ThemeError> {
+    let invalid = || ThemeError::Invalid(format!("`{value}` is not a `#rgb` or `#rrggbb` color"));
+
+    let digits = value.strip_prefix('#').ok_or_else(invalid)?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+    match digits.len() {
+        3 => {
+            let mut rgb = [0; 3];
+            for (i, digit) in digits.char_indices() {
+                rgb[i] = channel(&digit.to_string().repeat(2))?;
+            }
+            Ok(rgb)
+        }
+        6 => Ok([
+            channel(&digits[0..2])?,
+            channel(&digits[2..4])?,
+            channel(&digits[4..6])?,
+        ]),
+        _ => Err(invalid()),
+    }
+}
+
+fn hex_palette(colors: [u32; 16]) -> [[u8; 3]; 16] {
+    colors.map(|hex| {
+        let [_, r, g, b] = hex.to_be_bytes();
+        [r, g, b]
+    })
+}
@@ -0,0 +1,454 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    consts::SGR_REGEX, custom::Definition, spec, style::Style, Reset, SpecVersion, Token, LINK_END,
+};
+
+/// A run of text or a zero-width code, as seen by the line layout.
+pub(crate) enum Piece<'a, C> {
+    Text(&'a str),
+    Code(C),
+}
+
+/// Tracks the styling while laying out lines, and writes it in the
+/// output format (Dahlia markup or ANSI).
+pub(crate) trait LineState {
+    type Code;
+
+    /// Updates the state with a code.
+    fn apply(&mut self, code: &Self::Code);
+    fn push_code(&self, code: &Self::Code, out: &mut String);
+    fn push_text(&self, text: &str, out: &mut String);
+    /// Writes the codes that restore the current styling at the start of a line.
+    fn push_prefix(&self, out: &mut String);
+    /// Writes the codes that close the current styling at the end of a line.
+    fn push_suffix(&self, out: &mut String);
+}
+
+/// A code in Dahlia markup.
+#[derive(Clone, Copy)]
+pub(crate) enum MarkupCode<'a> {
+    Token(Token<'a>),
+    /// A defined code, written back by name
+    Defined(&'a str, &'a Definition),
+}
+
+/// Dahlia markup, with the styling restored through codes and closed with a full reset.
+pub(crate) struct MarkupState<'a> {
+    pub style: Style,
+    pub marker: char,
+    // the codes are written with the codes of this version
+    pub spec: SpecVersion,
+    // the URL of the open link, which is closed at the end of every line
+    pub link: Option<&'a str>,
+    // defined codes with SGR parameters since the last full reset, which can
+    // only be restored by writing them again
+    pub defined: Vec<(&'a str, &'a Definition)>,
+}
+
+impl<'a> MarkupState<'a> {
+    fn push_token(&self, token: &Token<'a>, out: &mut String) {
+        match (self.spec, token) {
+            (SpecVersion::Dahlia2, token) => out.push_str(&token.to_markup(self.marker)),
+            (SpecVersion::Dahlia1, token) => {
+                if let Some(markup) = spec::dahlia1_markup(token, self.marker) {
+                    out.push_str(&markup);
+                }
+            }
+        }
+    }
+
+    fn push_defined(&self, name: &str, out: &mut String) {
+        out.push(self.marker);
+        if name.chars().count() == 1 {
+            out.push_str(name);
+        } else {
+            out.push('[');
+            out.push_str(name);
+            out.push(']');
+        }
+    }
+}
+
+impl<'a> LineState for MarkupState<'a> {
+    type Code = MarkupCode<'a>;
+
+    fn apply(&mut self, code: &Self::Code) {
+        match *code {
+            MarkupCode::Token(Token::Link(url)) => self.link = Some(url),
+            MarkupCode::Token(Token::LinkEnd) => self.link = None,
+            MarkupCode::Token(token) => {
+                if token == Token::Reset(Reset::All) {
+                    self.defined.clear();
+                }
+                self.style.apply(&token);
+            }
+            MarkupCode::Defined(name, definition) => {
+                for token in &definition.tokens {
+                    self.apply(&MarkupCode::Token(*token));
+                }
+                if !definition.sgr.is_empty() {
+                    self.defined.push((name, definition));
+                }
+            }
+        }
+    }
+
+    fn push_code(&self, code: &Self::Code, out: &mut String) {
+        match code {
+            MarkupCode::Token(token) => self.push_token(token, out),
+            MarkupCode::Defined(name, _) => self.push_defined(name, out),
+        }
+    }
+
+    fn push_text(&self, text: &str, out: &mut String) {
+        // markers in text aren't codes (they would have been parsed as such)
+        for ch in text.chars() {
+            out.push(ch);
+            if ch == self.marker {
+                out.push('_');
+            }
+        }
+    }
+
+    fn push_prefix(&self, out: &mut String) {
+        // the defined codes come first, what else they set is then undone
+        // where the styling has changed since (Dahlia 1.x has no partial
+        // resets to undo it with)
+        let mut defined = Style::default();
+        for (name, definition) in &self.defined {
+            self.push_defined(name, out);
+            definition
+                .tokens
+                .iter()
+                .for_each(|token| defined.apply(token));
+        }
+
+        for token in defined.resets_to(&self.style).chain(self.style.tokens()) {
+            self.push_token(&token, out);
+        }
+        if let Some(url) = self.link {
+            self.push_token(&Token::Link(url), out);
+        }
+    }
+
+    fn push_suffix(&self, out: &mut String) {
+        if self.link.is_some() {
+            self.push_token(&Token::LinkEnd, out);
+        }
+        if !self.style.is_plain() || !self.defined.is_empty() {
+            self.push_token(&Token::Reset(Reset::All), out);
+        }
+    }
+}
+
+/// ANSI output, restoring the styling by repeating every SGR sequence since the last reset.
+#[derive(Default)]
+pub(crate) struct AnsiState {
+    active: Vec<String>,
+    // the OSC 8 sequence of the open link, which is closed at the end of every line
+    link: Option<String>,
+}
+
+impl LineState for AnsiState {
+    type Code = String;
+
+    fn apply(&mut self, code: &Self::Code) {
+        let Some(captures) = SGR_REGEX.captures(code) else {
+            return;
+        };
+
+        if let Some(osc) = captures.name("osc") {
+            if let Some(url) = link_url(osc.as_str()) {
+                self.link = (!url.is_empty()).then(|| code.clone());
+            }
+            return;
+        }
+
+        let Some(params) = captures.name("params") else {
+            // not an SGR sequence, doesn't affect the styling
+            return;
+        };
+
+        let params = params.as_str();
+        let first = params.split(';').next().unwrap_or_default();
+
+        if first.is_empty() || first == "0" {
+            self.active.clear();
+        }
+        if !params.is_empty() && params != "0" {
+            self.active.push(code.clone());
+        }
+    }
+
+    fn push_code(&self, code: &Self::Code, out: &mut String) {
+        out.push_str(code);
+    }
+
+    fn push_text(&self, text: &str, out: &mut String) {
+        out.push_str(text);
+    }
+
+    fn push_prefix(&self, out: &mut String) {
+        self.active.iter().for_each(|code| out.push_str(code));
+        if let Some(link) = &self.link {
+            out.push_str(link);
+        }
+    }
+
+    fn push_suffix(&self, out: &mut String) {
+        if self.link.is_some() {
+            out.push_str(LINK_END);
+        }
+        if !self.active.is_empty() {
+            out.push_str(crate::RESET);
+        }
+    }
+}
+
+/// Returns the URL of an OSC 8 hyperlink sequence (empty for the end of a
+/// link), given the content of the OSC sequence.
+pub(crate) fn link_url(osc: &str) -> Option<&str> {
+    // `8;params;url`, the parameters (like `id=...`) are optional
+    let (_, url) = osc.strip_prefix("8;")?.split_once(';')?;
+    Some(url)
+}
+
+/// Splits a string with ANSI codes into text and codes.
+pub(crate) fn ansi_pieces(string: &str) -> Vec<Piece<'_, String>> {
+    let mut pieces = Vec::new();
+    let mut last_match = 0;
+
+    for sequence in SGR_REGEX.find_iter(string) {
+        if sequence.start() > last_match {
+            pieces.push(Piece::Text(&string[last_match..sequence.start()]));
+        }
+        pieces.push(Piece::Code(sequence.as_str().to_owned()));
+        last_match = sequence.end();
+    }
+
+    if last_match < string.len() {
+        pieces.push(Piece::Text(&string[last_match..]));
+    }
+
+    pieces
+}
+
+enum Atom<'a, C> {
+    Code(C),
+    Space(&'a str),
+    Newline,
+    Glyph(&'a str),
+}
+
+impl<C> Atom<'_, C> {
+    fn width(&self) -> usize {
+        match self {
+            Self::Space(text) | Self::Glyph(text) => text.width(),
+            Self::Code(_) | Self::Newline => 0,
+        }
+    }
+}
+
+/// Greedily wraps the pieces into lines of at most `width` columns.
+///
+/// Lines are broken at whitespace, words longer than a line are broken
+/// between graphemes. Every line restores the styling in effect at its start
+/// and closes it at its end.
+pub(crate) fn wrap<'a, S: LineState>(
+    pieces: impl IntoIterator<Item = Piece<'a, S::Code>>,
+    width: usize,
+    state: S,
+) -> Vec<String> {
+    let mut wrapper = Wrapper {
+        width,
+        state,
+        lines: Vec::new(),
+        line: String::new(),
+        line_width: 0,
+        started: false,
+        pending: Vec::new(),
+    };
+    let mut word = Vec::new();
+
+    for piece in pieces {
+        let atoms: Vec<_> = match piece {
+            Piece::Code(code) => vec![Atom::Code(code)],
+            Piece::Text(text) => text
+                .graphemes(true)
+                .map(|grapheme| match grapheme {
+                    "\n" | "\r\n" => Atom::Newline,
+                    _ if grapheme.chars().all(char::is_whitespace) => Atom::Space(grapheme),
+                    _ => Atom::Glyph(grapheme),
+                })
+                .collect(),
+        };
+
+        for atom in atoms {
+            match atom {
+                Atom::Glyph(_) => word.push(atom),
+                // codes stick to the word before the spaces, if there is one
+                Atom::Code(_) if wrapper.pending.is_empty() => word.push(atom),
+                Atom::Code(_) | Atom::Space(_) => {
+                    if !word.is_empty() {
+                        wrapper.place_word(std::mem::take(&mut word));
+                    }
+                    wrapper.pending.push(atom);
+                }
+                Atom::Newline => {
+                    wrapper.place_word(std::mem::take(&mut word));
+                    wrapper.break_line();
+                }
+            }
+        }
+    }
+
+    wrapper.place_word(word);
+    wrapper.break_line();
+    wrapper.lines
+}
+
+/// Cuts the pieces to at most `max_width` columns, followed by the ellipsis.
+///
+/// Codes up to the cut are kept, the ellipsis continues with the styling at
+/// the cut, and the result ends by closing the styling.
+pub(crate) fn truncate<'a, S: LineState>(
+    pieces: impl IntoIterator<Item = Piece<'a, S::Code>>,
+    ellipsis: Vec<Piece<'a, S::Code>>,
+    max_width: usize,
+    mut state: S,
+) -> String {
+    let ellipsis_width: usize = ellipsis
+        .iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.width(),
+            Piece::Code(_) => 0,
+        })
+        .sum();
+
+    let mut out = String::new();
+    let mut width = 0;
+
+    // returns false once the limit is reached, codes are held back in
+    // `codes` until the text after them fits
+    let mut push = |piece: Piece<'a, S::Code>,
+                    limit: usize,
+                    codes: &mut Vec<S::Code>,
+                    state: &mut S| match piece {
+        Piece::Code(code) => {
+            codes.push(code);
+            true
+        }
+        Piece::Text(text) => text.graphemes(true).all(|grapheme| {
+            let fits = width + grapheme.width() <= limit;
+            if fits {
+                for code in codes.drain(..) {
+                    state.push_code(&code, &mut out);
+                    state.apply(&code);
+                }
+                state.push_text(grapheme, &mut out);
+                width += grapheme.width();
+            }
+            fits
+        }),
+    };
+
+    let limit = max_width.saturating_sub(ellipsis_width);
+    let mut codes = Vec::new();
+    for piece in pieces {
+        if !push(piece, limit, &mut codes, &mut state) {
+            break;
+        }
+    }
+
+    // codes right at the cut only styled the text that was cut off
+    codes.clear();
+    for piece in ellipsis {
+        if !push(piece, max_width, &mut codes, &mut state) {
+            break;
+        }
+    }
+
+    state.push_suffix(&mut out);
+    out
+}
+
+struct Wrapper<'a, S: LineState> {
+    width: usize,
+    state: S,
+    lines: Vec<String>,
+    line: String,
+    line_width: usize,
+    // whether the styling has been restored at the start of the current line
+    started: bool,
+    // spaces (and codes between them) before the next word
+    pending: Vec<Atom<'a, S::Code>>,
+}
+
+impl<'a, S: LineState> Wrapper<'a, S> {
+    fn place_word(&mut self, word: Vec<Atom<'a, S::Code>>) {
+        let pending = std::mem::take(&mut self.pending);
+        let pending_width: usize = pending.iter().map(Atom::width).sum();
+        let word_width: usize = word.iter().map(Atom::width).sum();
+
+        if word_width == 0 {
+            // trailing spaces at the end of a line are dropped
+            pending
+                .into_iter()
+                .chain(word)
+                .filter(|atom| matches!(atom, Atom::Code(_)))
+                .for_each(|atom| self.push(atom));
+            return;
+        }
+
+        if self.line_width > 0 && self.line_width + pending_width + word_width > self.width {
+            // the spaces at the break are dropped, their codes are kept
+            self.break_line();
+            for atom in pending {
+                if let Atom::Code(code) = atom {
+                    self.state.apply(&code);
+                }
+            }
+        } else {
+            pending.into_iter().for_each(|atom| self.push(atom));
+        }
+
+        word.into_iter().for_each(|atom| self.push(atom));
+    }
+
+    fn push(&mut self, atom: Atom<'a, S::Code>) {
+        let width = atom.width();
+
+        // a word longer than the line is broken between graphemes
+        if width > 0 && self.line_width > 0 && self.line_width + width > self.width {
+            self.break_line();
+        }
+
+        if !self.started {
+            self.state.push_prefix(&mut self.line);
+            self.started = true;
+        }
+
+        match atom {
+            Atom::Code(code) => {
+                self.state.push_code(&code, &mut self.line);
+                self.state.apply(&code);
+            }
+            Atom::Space(text) | Atom::Glyph(text) => self.state.push_text(text, &mut self.line),
+            Atom::Newline => unreachable!("newlines are handled by breaking the line"),
+        }
+
+        self.line_width += width;
+    }
+
+    fn break_line(&mut self) {
+        if self.started {
+            self.state.push_suffix(&mut self.line);
+        }
+
+        self.lines.push(std::mem::take(&mut self.line));
+        self.line_width = 0;
+        self.started = false;
+    }
+}
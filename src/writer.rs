@@ -0,0 +1,356 @@
+use std::{
+    io::{self, Write},
+    str,
+};
+
+use crate::{gradient, scan::Code, Dahlia, SpecVersion};
+
+// marker + "~[lightgoldenrodyellow", the longest color name
+const MAX_CODE_LEN: usize = 23;
+
+/// A writer that converts Dahlia codes on the fly, created by [`Dahlia::writer`].
+///
+/// Codes (and UTF-8 characters) split across `write` calls are held back until
+/// they are complete, and so is the text following a gradient until the
+/// gradient ends, as its colors depend on the length of the text. The
+/// automatic reset is only written by [`finish`](Self::finish) and when the
+/// writer is dropped, so the styling carries over a `flush`. The output is the
+/// same as [`convert`](Dahlia::convert) of everything written, including the
+/// reset when nothing was written.
+///
+/// Like with [`BufWriter`](std::io::BufWriter), once `write` has taken the
+/// bytes it returns `Ok`, and an error of the underlying writer is returned by
+/// the next call instead. The output that couldn't be written is kept, so
+/// retrying never writes anything twice.
+///
+/// ### Example
+/// ```rust
+/// # use std::io::Write;
+/// # use dahlia::{Dahlia, Depth};
+/// let dahlia = Dahlia::default().with_depth(Depth::High);
+/// let mut writer = dahlia.writer(Vec::new());
+///
+/// writer.write_all(b"&#ff").unwrap();
+/// writer.write_all(b"00ff;pink").unwrap();
+///
+/// let output = writer.finish().unwrap();
+/// assert_eq!(output, b"\x1b[38;2;255;0;255mpink\x1b[0m");
+/// ```
+pub struct DahliaWriter<'d, W: Write> {
+    dahlia: &'d Dahlia,
+    // only None after `finish`
+    inner: Option<W>,
+    // bytes that haven't been decoded yet, i.e. an incomplete character
+    pending: Vec<u8>,
+    // decoded text that can't be converted yet
+    text: String,
+    // converted bytes that haven't been written to `inner` yet
+    output: Vec<u8>,
+    // offset in `text` up to which codes have been looked at
+    scanned: usize,
+    // offsets in `text` of the open foreground and background gradients
+    gradients: [Option<usize>; 2],
+    // whether the output written so far has to be followed by a reset, like
+    // with `convert` that is the case for an empty output too
+    needs_reset: bool,
+}
+
+impl<'d, W: Write> DahliaWriter<'d, W> {
+    pub fn new(dahlia: &'d Dahlia, inner: W) -> Self {
+        Self {
+            dahlia,
+            inner: Some(inner),
+            pending: Vec::new(),
+            text: String::new(),
+            output: Vec::new(),
+            scanned: 0,
+            gradients: [None, None],
+            needs_reset: true,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner
+            .as_ref()
+            .expect("the writer should not be finished")
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly bypasses the conversion.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner
+            .as_mut()
+            .expect("the writer should not be finished")
+    }
+
+    /// Writes out any held back text and the automatic reset, flushes,
+    /// and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end();
+        self.flush()?;
+
+        Ok(self
+            .inner
+            .take()
+            .expect("the writer should not be finished"))
+    }
+
+    /// Converts everything that was held back and adds the automatic reset.
+    fn end(&mut self) {
+        self.process(true);
+
+        if let Some(reset) = self.dahlia.auto_reset_code() {
+            if self.needs_reset {
+                self.output.extend_from_slice(reset.as_bytes());
+                self.needs_reset = false;
+            }
+        }
+    }
+
+    /// Converts as much of the pending bytes as possible into `output`.
+    ///
+    /// When `finish` is true, nothing is held back anymore.
+    fn process(&mut self, finish: bool) {
+        loop {
+            // only the new bytes are decoded, the text before them is valid
+            let (valid, invalid) = match str::from_utf8(&self.pending) {
+                Ok(text) => (text.len(), None),
+                Err(error) => match error.error_len() {
+                    // an incomplete character at the end, wait for the rest
+                    None if !finish => (error.valid_up_to(), None),
+                    len => (
+                        error.valid_up_to(),
+                        Some(len.unwrap_or(self.pending.len() - error.valid_up_to())),
+                    ),
+                },
+            };
+            self.text.push_str(
+                str::from_utf8(&self.pending[..valid])
+                    .expect("the bytes up to valid_up_to should be valid UTF-8"),
+            );
+            self.pending.drain(..valid);
+
+            let end = if finish || invalid.is_some() {
+                // the open gradients end here
+                self.gradients = [None, None];
+                self.text.len()
+            } else {
+                // only the text added since the previous call is looked at
+                let complete = incomplete_code_start(&self.text, self.scanned, self.dahlia)
+                    .unwrap_or(self.text.len());
+                scan_gradients(
+                    &self.text[self.scanned..complete],
+                    self.scanned,
+                    self.dahlia,
+                    &mut self.gradients,
+                );
+                self.scanned = complete;
+
+                self.gradients
+                    .into_iter()
+                    .flatten()
+                    .fold(complete, usize::min)
+            };
+
+            let converted = self.dahlia.convert_without_reset(&self.text[..end]);
+            if !converted.is_empty() {
+                self.needs_reset = !converted.ends_with(crate::RESET);
+            }
+            self.output.extend_from_slice(converted.as_bytes());
+
+            self.text.drain(..end);
+            self.scanned = self.scanned.saturating_sub(end);
+            for gradient in self.gradients.iter_mut().flatten() {
+                *gradient -= end;
+            }
+
+            match invalid {
+                // invalid bytes are passed through unchanged
+                Some(len) => {
+                    self.output.extend(self.pending.drain(..len));
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Writes `output` to the inner writer, removing what was written, so
+    /// that nothing is written twice if this is called again after an error.
+    fn write_output(&mut self) -> io::Result<()> {
+        let inner = self
+            .inner
+            .as_mut()
+            .expect("the writer should not be finished");
+
+        while !self.output.is_empty() {
+            match inner.write(&self.output) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write the converted output",
+                    ));
+                }
+                Ok(len) => {
+                    self.output.drain(..len);
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DahliaWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // output left over from a failed call is written first, so that an
+        // error is returned before `buf` is taken
+        self.write_output()?;
+
+        self.pending.extend_from_slice(buf);
+        self.process(false);
+
+        // `buf` is buffered at this point, like with `BufWriter` an error is
+        // returned by the next call
+        let _ = self.write_output();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_output()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for DahliaWriter<'_, W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            // errors can't be reported from drop, `finish` should be used to handle them
+            self.end();
+            let _ = self.flush();
+        }
+    }
+}
+
+/// Finds the start of a code at the end of `text` that could still be
+/// completed by the following bytes. Such a code can't start before
+/// `scanned`, the end of the text that was complete in the previous call.
+fn incomplete_code_start(text: &str, scanned: usize, dahlia: &Dahlia) -> Option<usize> {
+    let marker = dahlia.marker;
+    // markers that are part of a complete code (like the second one of `__`
+    // when `_` is the marker) don't start another one, unless the code is in
+    // the URL of a link that isn't complete yet
+    let complete = dahlia
+        .scanner()
+        .codes(text)
+        .last()
+        .map_or(0, |(_, span)| span.end);
+
+    let short = text
+        .char_indices()
+        .rev()
+        .take(MAX_CODE_LEN)
+        .take_while(|&(i, _)| i >= complete)
+        .filter(|&(i, ch)| ch == marker && is_code_prefix(&text[i + marker.len_utf8()..], dahlia))
+        .map(|(i, _)| i)
+        .last();
+
+    // gradients and links can be longer than other codes, and URLs can contain
+    // markers (which can look like complete codes), so they are looked up
+    // separately
+    let new = &text[scanned..];
+    let long = new
+        .rfind("[gradient:")
+        .max(new.rfind("[link:"))
+        .and_then(|found| {
+            let start = &text[..scanned + found];
+            let start = start.strip_suffix('~').unwrap_or(start);
+            let start = start.strip_suffix(marker)?.len();
+            is_code_prefix(&text[start + marker.len_utf8()..], dahlia).then_some(start)
+        });
+
+    short.into_iter().chain(long).min()
+}
+
+/// Updates the offsets of the open gradients with the codes of `text`,
+/// which starts at `offset` in the pending bytes.
+fn scan_gradients(text: &str, offset: usize, dahlia: &Dahlia, gradients: &mut [Option<usize>; 2]) {
+    for (code, span) in dahlia.scanner().codes(text) {
+        let tokens = match code {
+            Code::Gradient { background, .. } => {
+                end_gradient(gradients, background);
+                gradients[usize::from(background)] = Some(offset + span.start);
+                continue;
+            }
+            Code::Defined(name) => &dahlia.definitions[name].tokens[..],
+            Code::Token(ref token) => std::slice::from_ref(token),
+        };
+        for token in tokens {
+            for background in [false, true] {
+                if gradient::ends_at(background, token) {
+                    end_gradient(gradients, background);
+                }
+            }
+        }
+    }
+}
+
+/// Ends the open foreground or background gradient.
+fn end_gradient(gradients: &mut [Option<usize>; 2], background: bool) {
+    // the other gradient can have started within this one, the text from
+    // the start of this one still has to be converted together
+    if let Some(start) = gradients[usize::from(background)].take() {
+        if let Some(other) = &mut gradients[usize::from(!background)] {
+            *other = (*other).min(start);
+        }
+    }
+}
+
+fn is_code_prefix(code: &str, dahlia: &Dahlia) -> bool {
+    let code = code.strip_prefix(['~', '^']).unwrap_or(code);
+
+    if dahlia.named_colors || dahlia.definitions.keys().any(|name| name.len() > 1) {
+        if let Some(name) = code.strip_prefix('[') {
+            if name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+            {
+                return true;
+            }
+        }
+    }
+
+    match dahlia.spec {
+        SpecVersion::Dahlia1 => match code.strip_prefix("[#") {
+            Some(hex) => hex.len() <= 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+            None => code.is_empty() || code == "[",
+        },
+        SpecVersion::Dahlia2 if code.starts_with('[') => is_bracket_prefix(&code[1..]),
+        SpecVersion::Dahlia2 => match (code.strip_prefix('#'), code.strip_prefix('@')) {
+            (Some(hex), _) => {
+                hex.len() <= 6 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+            }
+            (_, Some(index)) => index.len() <= 3 && index.bytes().all(|b| b.is_ascii_digit()),
+            (None, None) => code.is_empty() || code == "r",
+        },
+    }
+}
+
+/// Whether `code` (after the `[`) could still become a gradient or link code.
+fn is_bracket_prefix(code: &str) -> bool {
+    if let Some(stops) = code.strip_prefix("gradient:") {
+        return stops
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f' | b'#' | b':'));
+    }
+    if let Some(url) = code.strip_prefix("link:") {
+        return !url.contains(|ch: char| ch == ']' || ch.is_whitespace());
+    }
+
+    ["gradient:", "rainbow", "link:", "/link"]
+        .iter()
+        .any(|name| name.starts_with(code))
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "cli")]
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn dahlia(args: &[&str], stdin: &str) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dahlia"));
+    command.env_remove("NO_COLOR");
+    run(command.args(args), stdin)
+}
+
+fn run(command: &mut Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(args: &[&str], stdin: &str) -> String {
+    let output = dahlia(args, stdin);
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn converts_arguments() {
+    assert_eq!(
+        stdout(&["--depth", "low", "&aok", "&lnow"], ""),
+        "\x1b[92mok \x1b[1mnow\x1b[0m\n"
+    );
+}
+
+#[test]
+fn converts_stdin_lines() {
+    assert_eq!(
+        stdout(&["convert", "--depth=24", "--no-reset"], "&#ff0000;a\nb\n"),
+        "\x1b[38;2;255;0;0ma\nb\n"
+    );
+}
+
+#[test]
+fn commands() {
+    assert_eq!(stdout(&["clean", "&2a&_lb"], ""), "a&lb\n");
+    assert_eq!(stdout(&["escape", "-m", "§", "§2a&b"], ""), "§_2a&b\n");
+    assert_eq!(stdout(&["clean-ansi"], "\x1b[1mbold\x1b[0m\n"), "bold\n");
+}
+
+#[test]
+fn auto_depth() {
+    // stdout is a pipe, so no colors
+    assert_eq!(stdout(&["--auto", "&ahi"], ""), "hi\n");
+}
+
+#[test]
+fn depth_overrides_no_color() {
+    let no_color = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_dahlia"));
+        String::from_utf8(run(command.env("NO_COLOR", "1").args(args), "").stdout).unwrap()
+    };
+
+    assert_eq!(no_color(&["--depth", "low", "&ahi"]), "\x1b[92mhi\x1b[0m\n");
+    assert_eq!(no_color(&["&ahi"]), "hi\n");
+}
+
+#[test]
+fn text_after_double_dash() {
+    assert_eq!(stdout(&["clean", "--", "-n", "&ax"], ""), "-n x\n");
+}
+
+#[test]
+fn preview() {
+    let preview = stdout(&["preview", "--depth", "low"], "");
+
+    assert!(preview.starts_with("Detected depth: "));
+    assert!(preview.contains("Formats and resets at low:"));
+}
+
+#[test]
+fn invalid_arguments() {
+    for args in [
+        &["--depth", "9"][..],
+        &["--depth"],
+        &["--marker", "ab"],
+        &["--auto", "--depth", "8"],
+        &["--bold"],
+        &["preview", "text"],
+    ] {
+        let output = dahlia(args, "");
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("dahlia: "));
+    }
+}